@@ -0,0 +1,115 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    future::poll_fn,
+    io::IoSlice,
+    net::SocketAddr,
+    task::{Context, Poll},
+};
+
+use tracing::trace;
+
+use crate::{
+    Error,
+    addr::TryToV4,
+    cmsg::{self, CmsgBuf},
+};
+
+/// Per-datagram metadata received alongside the payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// IPv4 TTL or IPv6 hop limit. Only reported after [`set_recv_ttl(true)`](crate::UdpSocket::set_recv_ttl).
+    pub ttl: Option<u8>,
+    /// Cumulative number of datagrams the kernel dropped on this socket before this one was
+    /// queued. Only reported after [`set_rxq_ovfl(true)`](crate::UdpSocket::set_rxq_ovfl), and
+    /// not until the first drop.
+    pub drops: Option<u32>,
+}
+
+impl RecvMeta {
+    fn parse(cmsg: &CmsgBuf) -> Self {
+        let mut meta = RecvMeta::default();
+        for (level, ty, data) in cmsg.iter() {
+            match (level, ty) {
+                (libc::IPPROTO_IP, libc::IP_TTL) | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    meta.ttl = cmsg::read_int(data).map(|ttl| ttl as u8);
+                }
+                (libc::SOL_SOCKET, libc::SO_RXQ_OVFL) => {
+                    meta.drops = cmsg::read_int(data).map(|drops| drops as u32);
+                }
+                _ => trace!(level, ty, "ignoring unknown control message"),
+            }
+        }
+        meta
+    }
+}
+
+// Mapped IPv4 destinations go through the IPv4 stack even on IPv6 sockets, so the TTL has to be
+// passed with IP_TTL, not IPV6_HOPLIMIT.
+fn ttl_cmsg(target: SocketAddr, ttl: u8) -> CmsgBuf {
+    let mut cmsg = CmsgBuf::default();
+    if target.try_to_ipv4().is_ipv4() {
+        cmsg.push(libc::IPPROTO_IP, libc::IP_TTL, ttl as libc::c_int);
+    } else {
+        cmsg.push(libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT, ttl as libc::c_int);
+    }
+    cmsg
+}
+
+impl crate::UdpSocket {
+    /// Enable IP_RECVTTL / IPV6_RECVHOPLIMIT (both on dualstack sockets), so that
+    /// [`recv_from_with_meta`](Self::recv_from_with_meta) reports the received TTL.
+    pub fn set_recv_ttl(&self, enable: bool) -> crate::Result<()> {
+        let value = enable as libc::c_int;
+        if self.bind_addr().is_ipv4() || self.is_dualstack() {
+            crate::sockopt::set(self.socket(), libc::IPPROTO_IP, libc::IP_RECVTTL, value)
+                .map_err(Error::SetRecvTtl)?;
+        }
+        if self.bind_addr().is_ipv6() {
+            crate::sockopt::set(
+                self.socket(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_RECVHOPLIMIT,
+                value,
+            )
+            .map_err(Error::SetRecvTtl)?;
+        }
+        Ok(())
+    }
+
+    pub async fn recv_from_with_meta(
+        &self,
+        buf: &mut [u8],
+    ) -> std::io::Result<(usize, SocketAddr, RecvMeta)> {
+        let (size, addr, cmsg) = cmsg::recvmsg(self.socket(), buf).await?;
+        Ok((size, addr.try_to_ipv4(), RecvMeta::parse(&cmsg)))
+    }
+
+    /// Send a datagram with the given TTL (IPv4) or hop limit (IPv6).
+    pub async fn send_to_with_ttl(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+        ttl: u8,
+    ) -> std::io::Result<usize> {
+        poll_fn(|cx| self.poll_send_to_with_ttl(cx, buf, target, ttl)).await
+    }
+
+    pub fn poll_send_to_with_ttl(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+        ttl: u8,
+    ) -> Poll<std::io::Result<usize>> {
+        let target = self.convert_addr_for_send(target);
+        cmsg::poll_sendmsg(
+            self.socket(),
+            cx,
+            &[IoSlice::new(buf)],
+            target,
+            &ttl_cmsg(target, ttl),
+        )
+    }
+}
@@ -0,0 +1,63 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+use crate::{BindOpts, UdpSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+fn bind(addr: SocketAddr) -> UdpSocket {
+    UdpSocket::bind_udp(addr, BindOpts::default()).unwrap()
+}
+
+async fn assert_ttl_received(sender: &UdpSocket, receiver: &UdpSocket, to: SocketAddr, ttl: u8) {
+    sender.send_to_with_ttl(b"hello", to, ttl).await.unwrap();
+    let mut buf = [0u8; 16];
+    let (sz, addr, meta) = timeout(TIMEOUT, receiver.recv_from_with_meta(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..sz], b"hello");
+    assert_eq!(addr.is_ipv4(), to.is_ipv4(), "{addr:?} should be canonical");
+    assert_eq!(meta.ttl, Some(ttl), "to={to:?}");
+}
+
+#[tokio::test]
+async fn test_ttl_dualstack() {
+    let sender = bind((Ipv6Addr::UNSPECIFIED, 0).into());
+    let receiver = bind((Ipv6Addr::UNSPECIFIED, 0).into());
+    assert!(sender.is_dualstack() && receiver.is_dualstack());
+    receiver.set_recv_ttl(true).unwrap();
+
+    let port = receiver.bind_addr().port();
+    assert_ttl_received(&sender, &receiver, (Ipv4Addr::LOCALHOST, port).into(), 255).await;
+    assert_ttl_received(&sender, &receiver, (Ipv6Addr::LOCALHOST, port).into(), 1).await;
+    assert_ttl_received(&sender, &receiver, (Ipv4Addr::LOCALHOST, port).into(), 7).await;
+}
+
+#[tokio::test]
+async fn test_ttl_ipv4() {
+    let sender = bind((Ipv4Addr::LOCALHOST, 0).into());
+    let receiver = bind((Ipv4Addr::LOCALHOST, 0).into());
+    receiver.set_recv_ttl(true).unwrap();
+
+    assert_ttl_received(&sender, &receiver, receiver.bind_addr(), 255).await;
+}
+
+#[tokio::test]
+async fn test_ttl_not_reported_by_default() {
+    let sender = bind((Ipv4Addr::LOCALHOST, 0).into());
+    let receiver = bind((Ipv4Addr::LOCALHOST, 0).into());
+
+    sender
+        .send_to_with_ttl(b"hello", receiver.bind_addr(), 3)
+        .await
+        .unwrap();
+    let mut buf = [0u8; 16];
+    let (_, _, meta) = timeout(TIMEOUT, receiver.recv_from_with_meta(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(meta.ttl, None);
+}
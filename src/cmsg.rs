@@ -0,0 +1,144 @@
+//! Helpers for sendmsg()/recvmsg() with ancillary data.
+
+use std::{
+    io::IoSlice,
+    mem::MaybeUninit,
+    net::SocketAddr,
+    task::{Context, Poll},
+};
+
+use socket2::{MaybeUninitSlice, MsgHdr, MsgHdrMut, SockAddr, SockAddrStorage, SockRef};
+
+const CMSG_BUF_LEN: usize = 128;
+
+// cmsghdr needs to be aligned to size_t.
+#[repr(C, align(8))]
+pub(crate) struct CmsgBuf {
+    buf: [MaybeUninit<u8>; CMSG_BUF_LEN],
+    len: usize,
+}
+
+impl Default for CmsgBuf {
+    fn default() -> Self {
+        Self {
+            buf: [MaybeUninit::new(0); CMSG_BUF_LEN],
+            len: 0,
+        }
+    }
+}
+
+impl CmsgBuf {
+    pub fn push<T: Copy>(&mut self, level: libc::c_int, ty: libc::c_int, value: T) {
+        let data_len = std::mem::size_of::<T>() as u32;
+        let space = unsafe { libc::CMSG_SPACE(data_len) } as usize;
+        assert!(self.len + space <= CMSG_BUF_LEN, "cmsg buffer overflow");
+        unsafe {
+            let hdr = self.buf.as_mut_ptr().add(self.len).cast::<libc::cmsghdr>();
+            hdr.write_unaligned(libc::cmsghdr {
+                cmsg_len: libc::CMSG_LEN(data_len) as _,
+                cmsg_level: level,
+                cmsg_type: ty,
+            });
+            libc::CMSG_DATA(hdr).cast::<T>().write_unaligned(value);
+        }
+        self.len += space;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.buf.as_ptr().cast(), self.len) }
+    }
+
+    /// Iterate over (level, type, data) of received control messages.
+    pub fn iter(&self) -> impl Iterator<Item = (libc::c_int, libc::c_int, &[u8])> + '_ {
+        let mut mhdr: libc::msghdr = unsafe { std::mem::zeroed() };
+        mhdr.msg_control = self.buf.as_ptr() as *mut _;
+        mhdr.msg_controllen = self.len as _;
+        let mut cur = if self.len == 0 {
+            std::ptr::null()
+        } else {
+            unsafe { libc::CMSG_FIRSTHDR(&mhdr) }
+        };
+        std::iter::from_fn(move || {
+            if cur.is_null() {
+                return None;
+            }
+            let hdr = unsafe { cur.read_unaligned() };
+            let data_len = hdr.cmsg_len as usize - unsafe { libc::CMSG_LEN(0) } as usize;
+            let data = unsafe { std::slice::from_raw_parts(libc::CMSG_DATA(cur), data_len) };
+            cur = unsafe { libc::CMSG_NXTHDR(&mhdr, cur) };
+            Some((hdr.cmsg_level, hdr.cmsg_type, data))
+        })
+    }
+}
+
+pub(crate) fn read_int(data: &[u8]) -> Option<libc::c_int> {
+    Some(libc::c_int::from_ne_bytes(data.try_into().ok()?))
+}
+
+/// Non-blocking sendmsg() with control messages, registering for wakeup on WouldBlock.
+pub(crate) fn poll_sendmsg(
+    sock: &tokio::net::UdpSocket,
+    cx: &mut Context<'_>,
+    bufs: &[IoSlice<'_>],
+    target: SocketAddr,
+    cmsg: &CmsgBuf,
+) -> Poll<std::io::Result<usize>> {
+    let sref = SockRef::from(sock);
+    let addr = SockAddr::from(target);
+    loop {
+        std::task::ready!(sock.poll_send_ready(cx))?;
+        // try_io clears the readiness on WouldBlock, so the next poll_send_ready() waits.
+        let res = sock.try_io(tokio::io::Interest::WRITABLE, || {
+            let msg = MsgHdr::new()
+                .with_addr(&addr)
+                .with_buffers(bufs)
+                .with_control(cmsg.as_bytes());
+            sref.sendmsg(&msg, 0)
+        });
+        match res {
+            Ok(sz) => return Poll::Ready(Ok(sz)),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Poll::Ready(Err(e)),
+        }
+    }
+}
+
+/// Receive a datagram together with its control messages.
+pub(crate) async fn recvmsg(
+    sock: &tokio::net::UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr, CmsgBuf)> {
+    let sref = SockRef::from(sock);
+    sock.async_io(tokio::io::Interest::READABLE, || {
+        let mut cmsg = CmsgBuf::default();
+        let mut addr = unsafe {
+            SockAddr::new(
+                SockAddrStorage::zeroed(),
+                std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+            )
+        };
+        let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        let mut bufs = [MaybeUninitSlice::new(buf)];
+        let mut msg = MsgHdrMut::new()
+            .with_addr(&mut addr)
+            .with_buffers(&mut bufs)
+            .with_control(&mut cmsg.buf);
+        let sz = sref.recvmsg(&mut msg, 0)?;
+        cmsg.len = msg.control_len();
+        let addr = addr
+            .as_socket()
+            .ok_or_else(|| std::io::Error::other("recvmsg returned a non-IP address"))?;
+        Ok((sz, addr, cmsg))
+    })
+    .await
+}
+
+/// Non-blocking read of one message from the socket error queue (MSG_ERRQUEUE), returning its
+/// control messages. Fails with WouldBlock when the queue is empty.
+pub(crate) fn recv_errqueue(sock: SockRef<'_>) -> std::io::Result<CmsgBuf> {
+    let mut cmsg = CmsgBuf::default();
+    let mut msg = MsgHdrMut::new().with_control(&mut cmsg.buf);
+    sock.recvmsg(&mut msg, libc::MSG_ERRQUEUE)?;
+    cmsg.len = msg.control_len();
+    Ok(cmsg)
+}
@@ -0,0 +1,47 @@
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+use crate::Error;
+
+#[cfg(target_os = "linux")]
+const AVAILABLE_PATH: &str = "/proc/sys/net/ipv4/tcp_available_congestion_control";
+
+/// Names of the TCP congestion control algorithms the kernel has loaded.
+#[cfg(target_os = "linux")]
+pub fn available_tcp_congestion() -> crate::Result<Vec<String>> {
+    let list = std::fs::read_to_string(AVAILABLE_PATH).map_err(Error::TcpCongestion)?;
+    Ok(list.split_whitespace().map(str::to_owned).collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn available_tcp_congestion() -> crate::Result<Vec<String>> {
+    Err(Error::TcpCongestionNotSupported)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn set_tcp_congestion(sock: &socket2::Socket, name: &str) -> crate::Result<()> {
+    let unavailable = |available| Error::TcpCongestionUnavailable {
+        name: name.to_owned(),
+        available,
+    };
+    let available = available_tcp_congestion()?;
+    if !available.iter().any(|a| a == name) {
+        return Err(unavailable(available));
+    }
+    tracing::trace!(name, "setting TCP_CONGESTION");
+    sock.set_tcp_congestion(name.as_bytes())
+        .map_err(|e| match e.raw_os_error() {
+            // Raced with the module being unloaded.
+            Some(libc::ENOENT) => unavailable(available),
+            // Unprivileged processes may only use net.ipv4.tcp_allowed_congestion_control.
+            Some(libc::EPERM) => Error::TcpCongestionNotAllowed {
+                name: name.to_owned(),
+            },
+            _ => Error::TcpCongestion(e),
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_tcp_congestion(_sock: &socket2::Socket, _name: &str) -> crate::Result<()> {
+    Err(Error::TcpCongestionNotSupported)
+}
@@ -0,0 +1,66 @@
+use std::net::Ipv4Addr;
+
+use crate::{
+    BindOpts, ConnectOpts, Error, TcpListener, TcpStreamExt, available_tcp_congestion, tcp_connect,
+};
+
+/// "reno" is always built in.
+const RENO: &str = "reno";
+
+#[tokio::test]
+async fn test_tcp_congestion() {
+    assert!(
+        available_tcp_congestion()
+            .unwrap()
+            .iter()
+            .any(|a| a == RENO)
+    );
+    let listener = TcpListener::bind_tcp(
+        (Ipv4Addr::LOCALHOST, 0).into(),
+        BindOpts {
+            tcp_congestion: Some(RENO),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let connect = tcp_connect(
+        listener.bind_addr(),
+        ConnectOpts {
+            tcp_congestion: Some(RENO),
+            ..Default::default()
+        },
+    );
+    let (stream, accepted) = tokio::join!(connect, listener.accept());
+    assert_eq!(stream.unwrap().tcp_congestion().unwrap(), RENO);
+    // Accepted streams inherit the listener's algorithm.
+    assert_eq!(accepted.unwrap().0.tcp_congestion().unwrap(), RENO);
+}
+
+#[tokio::test]
+async fn test_tcp_congestion_unavailable() {
+    let res = tcp_connect(
+        (Ipv4Addr::LOCALHOST, 1).into(),
+        ConnectOpts {
+            tcp_congestion: Some("no-such-algo"),
+            ..Default::default()
+        },
+    )
+    .await;
+    match res {
+        Err(Error::TcpCongestionUnavailable { name, available }) => {
+            assert_eq!(name, "no-such-algo");
+            assert!(available.iter().any(|a| a == RENO));
+        }
+        res => panic!("{res:?}"),
+    }
+
+    let res = TcpListener::bind_tcp(
+        (Ipv4Addr::LOCALHOST, 0).into(),
+        BindOpts {
+            tcp_congestion: Some("no-such-algo"),
+            ..Default::default()
+        },
+    );
+    assert!(matches!(res, Err(Error::TcpCongestionUnavailable { .. })));
+}
@@ -1,42 +1,190 @@
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(test)]
+mod tests;
+
+mod dialer;
+mod happy_eyeballs;
+mod hole_punch;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
 
 use socket2::SockRef;
 
-use crate::{Error, bind_device::BindDevice};
+use crate::{
+    Error,
+    bind_device::BindDevice,
+    proxy::{NoProxy, Proxy, ProxyTarget},
+};
+
+pub use dialer::{DialSource, DialStrategy, DialedStream, Dialer, DialerOpts};
+pub use happy_eyeballs::{CONNECTION_ATTEMPT_DELAY, ConnectTarget, tcp_connect_happy_eyeballs};
+pub use hole_punch::{HolePunchOpts, tcp_hole_punch};
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct ConnectOpts<'a> {
     pub source_port: Option<u16>,
+    /// Local address to connect from. Must be of the same family as the destination.
+    pub source_addr: Option<IpAddr>,
     pub bind_device: Option<&'a BindDevice>,
+    /// Give up connecting after this long with [`Error::ConnectTimeout`].
+    pub timeout: Option<Duration>,
+    /// Linux only: how many times to retransmit the SYN before giving up (TCP_SYNCNT).
+    pub syn_retries: Option<u8>,
+    /// Connect through this proxy. The other options apply to the connection to the proxy.
+    pub proxy: Option<&'a Proxy>,
+    /// Destinations to connect to directly, bypassing `proxy`.
+    pub no_proxy: Option<&'a NoProxy>,
+    /// Linux only: TCP Fast Open (TCP_FASTOPEN_CONNECT). The SYN goes out with the first write,
+    /// see [`tcp_connect_with_data`](crate::tcp_connect_with_data). Ignored on other systems.
+    pub fast_open: bool,
+    /// Use Multipath TCP if the kernel supports it, plain TCP otherwise. See
+    /// [`TcpStreamExt::is_mptcp`](crate::TcpStreamExt::is_mptcp).
+    pub mptcp: bool,
+    /// Linux only: firewall mark for policy routing (SO_MARK). Requires CAP_NET_ADMIN.
+    pub fwmark: Option<u32>,
+    /// Linux only: congestion control algorithm (TCP_CONGESTION), e.g. "bbr".
+    pub tcp_congestion: Option<&'a str>,
+}
+
+impl ConnectOpts<'_> {
+    fn proxy_for(&self, bypass: impl FnOnce(&NoProxy) -> bool) -> Option<&Proxy> {
+        let proxy = self.proxy?;
+        match self.no_proxy {
+            Some(no_proxy) if bypass(no_proxy) => None,
+            _ => Some(proxy),
+        }
+    }
 }
 
 pub async fn tcp_connect<'a>(
     addr: SocketAddr,
     opts: ConnectOpts<'a>,
 ) -> crate::Result<tokio::net::TcpStream> {
-    let (sock, bind_addr) = if addr.is_ipv6() {
-        (
-            tokio::net::TcpSocket::new_v6().map_err(Error::SocketNew)?,
-            SocketAddr::from((Ipv6Addr::UNSPECIFIED, opts.source_port.unwrap_or(0))),
-        )
+    match opts.proxy_for(|n| n.matches_ip(addr.ip())) {
+        Some(proxy) => crate::proxy::connect_via(proxy, ProxyTarget::Addr(addr), opts).await,
+        None => tcp_connect_direct(addr, opts).await,
+    }
+}
+
+/// [`tcp_connect`] ignoring `opts.proxy`.
+pub(crate) async fn tcp_connect_direct(
+    addr: SocketAddr,
+    opts: ConnectOpts<'_>,
+) -> crate::Result<tokio::net::TcpStream> {
+    if let Some(source_addr) = opts.source_addr
+        && source_addr.is_ipv6() != addr.is_ipv6()
+    {
+        return Err(Error::SourceAddrFamilyMismatch { source_addr, addr });
+    }
+
+    let (domain, unspecified) = if addr.is_ipv6() {
+        (socket2::Domain::IPV6, IpAddr::V6(Ipv6Addr::UNSPECIFIED))
     } else {
-        (
-            tokio::net::TcpSocket::new_v4().map_err(Error::SocketNew)?,
-            SocketAddr::from((Ipv4Addr::UNSPECIFIED, opts.source_port.unwrap_or(0))),
-        )
+        (socket2::Domain::IPV4, IpAddr::V4(Ipv4Addr::UNSPECIFIED))
     };
+    let sock = crate::mptcp::tcp_socket(domain, opts.mptcp)?;
+    sock.set_nonblocking(true).map_err(Error::SetNonblocking)?;
+    let sock = tokio::net::TcpSocket::from_std_stream(std::net::TcpStream::from(sock));
+    let bind_addr = SocketAddr::new(
+        opts.source_addr.unwrap_or(unspecified),
+        opts.source_port.unwrap_or(0),
+    );
     let sref = SockRef::from(&sock);
 
     if let Some(bd) = opts.bind_device {
         bd.bind_sref(&sref, addr.is_ipv6())?;
     }
 
+    if let Some(mark) = opts.fwmark {
+        crate::fwmark::set_fwmark(&sref, mark)?;
+    }
+
     if bind_addr.port() > 0 {
         #[cfg(not(windows))]
         sref.set_reuse_port(true).map_err(Error::ReusePort)?;
         sref.set_reuse_address(true).map_err(Error::ReuseAddress)?;
         sref.bind(&bind_addr.into()).map_err(Error::Bind)?;
+    } else if opts.source_addr.is_some() {
+        // Let connect() pick the port, otherwise bind() reserves a whole ephemeral port for this
+        // address.
+        #[cfg(target_os = "linux")]
+        crate::sockopt::set(
+            &*sref,
+            libc::IPPROTO_IP,
+            libc::IP_BIND_ADDRESS_NO_PORT,
+            1 as libc::c_int,
+        )
+        .map_err(Error::BindAddressNoPort)?;
+        sref.bind(&bind_addr.into()).map_err(Error::Bind)?;
+    }
+
+    if let Some(syn_retries) = opts.syn_retries {
+        set_syn_retries(&sref, syn_retries)?;
+    }
+
+    if opts.fast_open {
+        crate::fastopen::set_connect(&sref)?;
+    }
+
+    if let Some(name) = opts.tcp_congestion {
+        crate::congestion::set_tcp_congestion(&sref, name)?;
+    }
+
+    // The socket is owned by the connect future, so on timeout it's closed when the future is
+    // dropped.
+    let connect = sock.connect(addr);
+    match opts.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| Error::ConnectTimeout)?
+            .map_err(Error::Connect),
+        None => connect.await.map_err(Error::Connect),
     }
+}
+
+/// Connect to `host` (a hostname or an IP address) and `port`.
+///
+/// With a proxy, the hostname is sent to the proxy to resolve, so it's never looked up locally.
+/// Without one, or if `no_proxy` matches the hostname, it's resolved locally and connected to
+/// with [`tcp_connect_happy_eyeballs`].
+pub async fn tcp_connect_host(
+    host: &str,
+    port: u16,
+    opts: ConnectOpts<'_>,
+) -> crate::Result<tokio::net::TcpStream> {
+    let unbracketed = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+        return tcp_connect(SocketAddr::new(ip, port), opts).await;
+    }
+    if let Some(proxy) = opts.proxy_for(|n| n.matches_host(host)) {
+        return crate::proxy::connect_via(proxy, ProxyTarget::Host(host, port), opts).await;
+    }
+    let host_port = format!("{host}:{port}");
+    let opts = ConnectOpts {
+        proxy: None,
+        ..opts
+    };
+    let (stream, _) = tcp_connect_happy_eyeballs(host_port.as_str(), opts).await?;
+    Ok(stream)
+}
+
+#[cfg(target_os = "linux")]
+fn set_syn_retries(sref: &socket2::Socket, syn_retries: u8) -> crate::Result<()> {
+    crate::sockopt::set(
+        sref,
+        libc::IPPROTO_TCP,
+        libc::TCP_SYNCNT,
+        syn_retries as libc::c_int,
+    )
+    .map_err(Error::SynRetries)
+}
 
-    sock.connect(addr).await.map_err(Error::Connect)
+#[cfg(not(target_os = "linux"))]
+fn set_syn_retries(_sref: &socket2::Socket, _syn_retries: u8) -> crate::Result<()> {
+    Err(Error::SynRetriesNotSupported)
 }
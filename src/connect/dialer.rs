@@ -0,0 +1,429 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use backon::{BackoffBuilder, ExponentialBackoff, ExponentialBuilder};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Semaphore,
+};
+use tracing::{debug, trace};
+
+use crate::{BindDevice, ConnectOpts, Error, addr::TryToV4, tcp_connect};
+
+/// A local address and/or interface outgoing connections can be made from.
+#[derive(Clone, Debug, Default)]
+pub struct DialSource {
+    pub bind_device: Option<BindDevice>,
+    pub source_addr: Option<IpAddr>,
+}
+
+impl DialSource {
+    fn can_reach(&self, addr: SocketAddr) -> bool {
+        self.source_addr
+            .is_none_or(|s| s.is_ipv6() == addr.is_ipv6())
+    }
+}
+
+/// How [`Dialer`] picks a source for each connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DialStrategy {
+    #[default]
+    RoundRobin,
+    /// The source with the fewest live connections.
+    LeastConnections,
+    /// The same peer IP always goes through the same source.
+    HashByPeer,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DialerOpts {
+    pub strategy: DialStrategy,
+    /// Max number of dials in progress at once. Further dials wait for a slot. `Some(0)` is the
+    /// same as `None` (no limit).
+    pub max_concurrent_dials: Option<usize>,
+    /// Max number of connections (live or being dialed) to one destination IP. Further dials fail
+    /// with [`Error::DestinationLimit`].
+    pub max_per_destination: Option<usize>,
+    /// Delays before an address that failed can be dialed again. Once the backoff runs out of
+    /// retries, the address isn't dialed again until its failure expires.
+    pub backoff: ExponentialBuilder,
+    /// How long a failure is remembered after the last failed dial.
+    pub failure_ttl: Duration,
+}
+
+impl Default for DialerOpts {
+    fn default() -> Self {
+        Self {
+            strategy: Default::default(),
+            max_concurrent_dials: None,
+            max_per_destination: None,
+            backoff: ExponentialBuilder::new().with_max_delay(Duration::from_secs(30)),
+            failure_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+struct SourceState {
+    source: DialSource,
+    live: Arc<AtomicUsize>,
+}
+
+struct Failure {
+    backoff: ExponentialBackoff,
+    retry_at: Instant,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+struct DialState {
+    in_progress: HashSet<SocketAddr>,
+    per_destination: HashMap<IpAddr, usize>,
+    failures: HashMap<SocketAddr, Failure>,
+}
+
+/// Spreads outgoing connections across a pool of [`DialSource`]s.
+///
+/// Addresses are compared in canonical form, so an IPv4-mapped IPv6 address is the same
+/// destination as the IPv4 address.
+///
+/// Only one dial to an address runs at a time. A second [`connect`](Self::connect) to an address
+/// that is still being dialed doesn't wait for or share the first dial's result (a stream has
+/// only one owner), it fails right away with [`Error::DialInProgress`]. Callers that want the
+/// connection should route it through whoever started the dial.
+pub struct Dialer {
+    sources: Vec<SourceState>,
+    opts: DialerOpts,
+    next: AtomicUsize,
+    dial_slots: Option<Semaphore>,
+    state: Arc<Mutex<DialState>>,
+}
+
+impl Dialer {
+    /// If `sources` is empty, connections are made without binding.
+    pub fn new(sources: Vec<DialSource>, opts: DialerOpts) -> Self {
+        let sources = if sources.is_empty() {
+            vec![DialSource::default()]
+        } else {
+            sources
+        };
+        Self {
+            sources: sources
+                .into_iter()
+                .map(|source| SourceState {
+                    source,
+                    live: Default::default(),
+                })
+                .collect(),
+            opts,
+            next: AtomicUsize::new(0),
+            dial_slots: opts
+                .max_concurrent_dials
+                .filter(|n| *n > 0)
+                .map(Semaphore::new),
+            state: Default::default(),
+        }
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &DialSource> {
+        self.sources.iter().map(|s| &s.source)
+    }
+
+    /// Number of live connections per source, in the same order as [`sources`](Self::sources).
+    pub fn live_connections(&self) -> Vec<usize> {
+        self.sources
+            .iter()
+            .map(|s| s.live.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    fn pick_source(&self, addr: SocketAddr) -> crate::Result<usize> {
+        let candidates = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.source.can_reach(addr))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return Err(Error::NoDialSource { addr });
+        }
+
+        let idx = match self.opts.strategy {
+            DialStrategy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            DialStrategy::LeastConnections => candidates
+                .iter()
+                .copied()
+                .min_by_key(|idx| self.sources[*idx].live.load(Ordering::Relaxed))
+                .unwrap(),
+            DialStrategy::HashByPeer => {
+                let mut hasher = DefaultHasher::new();
+                addr.try_to_ipv4().ip().hash(&mut hasher);
+                candidates[hasher.finish() as usize % candidates.len()]
+            }
+        };
+        Ok(idx)
+    }
+
+    /// Number of connections (live or being dialed) to each destination IP.
+    pub fn connections_per_destination(&self) -> HashMap<IpAddr, usize> {
+        self.state.lock().unwrap().per_destination.clone()
+    }
+
+    /// Forget past failures of `addr`, so that it can be dialed right away.
+    pub fn clear_failure(&self, addr: SocketAddr) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .remove(&addr.try_to_ipv4());
+    }
+
+    fn start_dial(&self, addr: SocketAddr) -> crate::Result<(InProgressGuard, DestinationGuard)> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.failures.retain(|_, f| f.expires_at > now);
+        if let Some(f) = state.failures.get(&addr)
+            && f.retry_at > now
+        {
+            return Err(Error::DialBackoff {
+                addr,
+                retry_in: f.retry_at - now,
+            });
+        }
+        if state.in_progress.contains(&addr) {
+            return Err(Error::DialInProgress { addr });
+        }
+        let ip = addr.ip();
+        let count = state.per_destination.get(&ip).copied().unwrap_or_default();
+        if let Some(limit) = self.opts.max_per_destination
+            && count >= limit
+        {
+            return Err(Error::DestinationLimit { ip, limit });
+        }
+        state.in_progress.insert(addr);
+        *state.per_destination.entry(ip).or_default() += 1;
+        Ok((
+            InProgressGuard {
+                state: self.state.clone(),
+                addr,
+            },
+            DestinationGuard {
+                state: self.state.clone(),
+                ip,
+            },
+        ))
+    }
+
+    fn record_failure(&self, addr: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let failure = state.failures.entry(addr).or_insert_with(|| Failure {
+            backoff: self.opts.backoff.build(),
+            retry_at: now,
+            expires_at: now,
+        });
+        let retry_in = failure.backoff.next().unwrap_or(self.opts.failure_ttl);
+        failure.retry_at = now + retry_in;
+        failure.expires_at = failure.retry_at.max(now + self.opts.failure_ttl);
+        trace!(?addr, ?retry_in, "backing off");
+    }
+
+    /// Connect to `addr` from the next source. `bind_device` and `source_addr` in `opts` are
+    /// overridden by the source.
+    ///
+    /// Fails right away with [`Error::DialBackoff`] if `addr` failed recently, and with
+    /// [`Error::DialInProgress`] if it's already being dialed, even if that dial ends up
+    /// succeeding.
+    pub async fn connect(
+        &self,
+        addr: SocketAddr,
+        opts: ConnectOpts<'_>,
+    ) -> crate::Result<DialedStream> {
+        let addr = addr.try_to_ipv4();
+        let (in_progress, destination) = self.start_dial(addr)?;
+        let _permit = match &self.dial_slots {
+            Some(slots) => Some(slots.acquire().await.unwrap()),
+            None => None,
+        };
+
+        let idx = self.pick_source(addr)?;
+        let state = &self.sources[idx];
+        // Count in-flight attempts too, so that LeastConnections doesn't pile onto one source.
+        let guard = LiveGuard::new(state.live.clone());
+        let opts = ConnectOpts {
+            bind_device: state.source.bind_device.as_ref(),
+            source_addr: state.source.source_addr,
+            ..opts
+        };
+        trace!(?addr, source=?state.source, "dialing");
+        let stream = match tcp_connect(addr, opts).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!(?addr, source=?state.source, "error dialing: {e:#}");
+                if is_peer_failure(&e) {
+                    self.record_failure(addr);
+                }
+                return Err(e);
+            }
+        };
+        self.state.lock().unwrap().failures.remove(&addr);
+        drop(in_progress);
+        Ok(DialedStream {
+            stream,
+            source: idx,
+            _guard: guard,
+            _destination: destination,
+        })
+    }
+}
+
+// Failures caused by the destination (or the path to it). Local misconfiguration, like a bad
+// source address or socket option, would fail the same way for every address, so it isn't held
+// against the peer.
+fn is_peer_failure(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Connect(..)
+            | Error::ConnectTimeout
+            | Error::Socks5GeneralFailure
+            | Error::Socks5NotAllowed
+            | Error::Socks5NetworkUnreachable
+            | Error::Socks5HostUnreachable
+            | Error::Socks5ConnectionRefused
+            | Error::Socks5TtlExpired
+            | Error::Socks5UnknownReply(..)
+            | Error::HttpProxyStatus { .. }
+    )
+}
+
+struct InProgressGuard {
+    state: Arc<Mutex<DialState>>,
+    addr: SocketAddr,
+}
+
+impl Drop for InProgressGuard {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().in_progress.remove(&self.addr);
+    }
+}
+
+struct DestinationGuard {
+    state: Arc<Mutex<DialState>>,
+    ip: IpAddr,
+}
+
+impl Drop for DestinationGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(count) = state.per_destination.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                state.per_destination.remove(&self.ip);
+            }
+        }
+    }
+}
+
+struct LiveGuard(Arc<AtomicUsize>);
+
+impl LiveGuard {
+    fn new(counter: Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for LiveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A connection made by [`Dialer`]. It counts as live for its source until dropped.
+pub struct DialedStream {
+    stream: tokio::net::TcpStream,
+    source: usize,
+    _guard: LiveGuard,
+    _destination: DestinationGuard,
+}
+
+impl std::fmt::Debug for DialedStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DialedStream")
+            .field("stream", &self.stream)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl DialedStream {
+    /// Index of the source in [`Dialer::sources`].
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    pub fn stream(&self) -> &tokio::net::TcpStream {
+        &self.stream
+    }
+
+    pub fn stream_mut(&mut self) -> &mut tokio::net::TcpStream {
+        &mut self.stream
+    }
+
+    /// Unwrap the stream. It's not counted as live after this.
+    pub fn into_inner(self) -> tokio::net::TcpStream {
+        self.stream
+    }
+}
+
+impl AsyncRead for DialedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for DialedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,144 @@
+use std::{net::SocketAddr, time::Duration};
+
+use futures::{
+    StreamExt,
+    future::{Either, select},
+    stream::FuturesUnordered,
+};
+use tracing::{debug, trace};
+
+use crate::{ConnectOpts, Error, addr::TryToV4, tcp_connect};
+
+/// "Connection Attempt Delay" recommended by RFC 8305.
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug)]
+pub enum ConnectTarget<'a> {
+    /// "host:port", resolved with the system resolver.
+    HostPort(&'a str),
+    Addrs(&'a [SocketAddr]),
+}
+
+impl<'a> From<&'a str> for ConnectTarget<'a> {
+    fn from(value: &'a str) -> Self {
+        ConnectTarget::HostPort(value)
+    }
+}
+
+impl<'a> From<&'a [SocketAddr]> for ConnectTarget<'a> {
+    fn from(value: &'a [SocketAddr]) -> Self {
+        ConnectTarget::Addrs(value)
+    }
+}
+
+impl ConnectTarget<'_> {
+    async fn resolve(&self) -> crate::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = match *self {
+            ConnectTarget::HostPort(host) => tokio::net::lookup_host(host)
+                .await
+                .map_err(Error::Resolve)?
+                .collect(),
+            ConnectTarget::Addrs(addrs) => addrs.to_vec(),
+        };
+        let mut canonical = Vec::with_capacity(addrs.len());
+        for addr in addrs.into_iter().map(|a| a.try_to_ipv4()) {
+            if !canonical.contains(&addr) {
+                canonical.push(addr);
+            }
+        }
+        Ok(canonical)
+    }
+}
+
+/// Alternate address families, starting with the family of the first address (RFC 8305 section 4).
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_is_v6 = first.is_ipv6();
+    let mut result = Vec::with_capacity(addrs.len());
+    let (preferred, other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first_is_v6);
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => result.extend(a.into_iter().chain(b)),
+        }
+    }
+    result
+}
+
+/// Connect to whichever address of `target` answers first (Happy Eyeballs v2, RFC 8305).
+///
+/// Attempts alternate between IPv6 and IPv4 and are started [`CONNECTION_ATTEMPT_DELAY`] apart, or
+/// immediately when the previous one fails. Once one succeeds, the rest are cancelled.
+/// Each attempt is made with [`tcp_connect`] and the same `opts`.
+pub async fn tcp_connect_happy_eyeballs(
+    target: impl Into<ConnectTarget<'_>>,
+    opts: ConnectOpts<'_>,
+) -> crate::Result<(tokio::net::TcpStream, SocketAddr)> {
+    let target = target.into();
+    let mut addrs = target.resolve().await?;
+    if let Some(source_addr) = opts.source_addr {
+        addrs.retain(|a| {
+            let keep = a.is_ipv6() == source_addr.is_ipv6();
+            if !keep {
+                trace!(addr=?a, ?source_addr, "happy eyeballs: skipping address of the other family");
+            }
+            keep
+        });
+    }
+    let addrs = interleave(addrs);
+    debug!(?target, ?addrs, "happy eyeballs: connecting");
+
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    let attempt = |addr: SocketAddr| async move {
+        trace!(?addr, "happy eyeballs: starting attempt");
+        (addr, tcp_connect(addr, opts).await)
+    };
+
+    loop {
+        if attempts.is_empty() {
+            match addrs.next() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => break,
+            }
+        }
+
+        let result = if !addrs.as_slice().is_empty() {
+            let delay = std::pin::pin!(tokio::time::sleep(CONNECTION_ATTEMPT_DELAY));
+            match select(attempts.next(), delay).await {
+                Either::Left((result, _)) => result,
+                Either::Right(..) => None,
+            }
+        } else {
+            attempts.next().await
+        };
+
+        match result {
+            Some((addr, Ok(stream))) => {
+                debug!(?addr, "happy eyeballs: connected");
+                return Ok((stream, addr));
+            }
+            Some((addr, Err(e))) => {
+                debug!(?addr, "happy eyeballs: attempt failed: {e:#}");
+                last_error = Some(e);
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+            // Attempt delay expired.
+            None => {
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or(Error::NoAddresses))
+}
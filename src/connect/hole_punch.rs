@@ -0,0 +1,140 @@
+use std::{net::SocketAddr, time::Duration};
+
+use futures::future::{Either, select};
+use tokio::{net::TcpStream, time::Instant};
+use tracing::{debug, trace};
+
+use crate::{ConnectOpts, Error, TcpListener, addr::TryToV4};
+
+use super::tcp_connect_direct;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HolePunchOpts<'a> {
+    /// Give up after this long with [`Error::ConnectTimeout`].
+    pub window: Duration,
+    /// A new connect attempt is started this often. Each attempt is abandoned when the next one
+    /// starts.
+    pub retry_interval: Duration,
+    /// Options for the connect attempts. `source_port` is always the listener's port, `proxy`
+    /// and `timeout` are ignored.
+    pub connect: ConnectOpts<'a>,
+}
+
+impl Default for HolePunchOpts<'_> {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            retry_interval: Duration::from_millis(500),
+            connect: Default::default(),
+        }
+    }
+}
+
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(10);
+
+// Errors that won't go away by retrying.
+fn is_fatal(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::SocketNew(..)
+            | Error::Bind(..)
+            | Error::BindAddressNoPort(..)
+            | Error::ReusePort(..)
+            | Error::ReuseAddress(..)
+            | Error::SourceAddrFamilyMismatch { .. }
+            | Error::BindDeviceNotSupported
+            | Error::BindDeviceSetDeviceError(..)
+            | Error::SynRetries(..)
+            | Error::SynRetriesNotSupported
+            | Error::Fwmark(..)
+            | Error::FwmarkPermissionDenied
+            | Error::FwmarkNotSupported
+            | Error::TcpCongestion(..)
+            | Error::TcpCongestionUnavailable { .. }
+            | Error::TcpCongestionNotAllowed { .. }
+            | Error::TcpCongestionNotSupported
+    )
+}
+
+/// Punch a hole through NATs towards `peer` by repeatedly connecting to it from the listener's
+/// port (TCP simultaneous open), while also accepting on the listener. Returns whichever
+/// connection to `peer` is established first. The peer is expected to do the same.
+///
+/// The listener must be bound with [`BindOpts::reuseport`](crate::BindOpts::reuseport), so that
+/// outgoing connects can share its port. Connections accepted from other addresses in the
+/// meantime are passed to `on_other_accept`.
+pub async fn tcp_hole_punch(
+    listener: &TcpListener,
+    peer: SocketAddr,
+    opts: HolePunchOpts<'_>,
+    mut on_other_accept: impl FnMut(TcpStream, SocketAddr),
+) -> crate::Result<(TcpStream, SocketAddr)> {
+    let peer = peer.try_to_ipv4();
+    let local = listener.bind_addr();
+    let mut connect_opts = ConnectOpts {
+        source_port: Some(local.port()),
+        timeout: Some(opts.retry_interval),
+        proxy: None,
+        ..opts.connect
+    };
+    // SYNs have to come from the address the peer punches towards.
+    let local_ip = local.ip().to_canonical();
+    if connect_opts.source_addr.is_none()
+        && !local_ip.is_unspecified()
+        && local_ip.is_ipv6() == peer.is_ipv6()
+    {
+        connect_opts.source_addr = Some(local_ip);
+    }
+    debug!(?local, ?peer, window=?opts.window, "hole punching");
+
+    let connecting = async {
+        loop {
+            let started = Instant::now();
+            match tcp_connect_direct(peer, connect_opts).await {
+                Ok(stream) => {
+                    debug!(?peer, "hole punching: connected");
+                    return Ok(stream);
+                }
+                Err(e) if is_fatal(&e) => return Err(e),
+                Err(e) => trace!(?peer, "hole punching: connect attempt failed: {e:#}"),
+            }
+            tokio::time::sleep_until(started + opts.retry_interval).await;
+        }
+    };
+
+    let accepting = async {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                // E.g. ECONNABORTED or EMFILE. Keep accepting until the window runs out, but don't
+                // spin while the error persists.
+                Err(e) => {
+                    debug!(?peer, "hole punching: error accepting: {e:#}");
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            if addr == peer {
+                debug!(?peer, "hole punching: accepted");
+                return Ok::<_, Error>(stream);
+            }
+            trace!(
+                ?addr,
+                ?peer,
+                "hole punching: accepted a connection from someone else"
+            );
+            on_other_accept(stream, addr);
+        }
+    };
+
+    let (connecting, accepting) = (std::pin::pin!(connecting), std::pin::pin!(accepting));
+    let raced = select(connecting, accepting);
+    let stream = match tokio::time::timeout(opts.window, raced).await {
+        Ok(Either::Left((res, _)) | Either::Right((res, _))) => res?,
+        Err(_) => {
+            debug!(?peer, "hole punching timed out");
+            return Err(Error::ConnectTimeout);
+        }
+    };
+    Ok((stream, peer))
+}
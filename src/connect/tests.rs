@@ -0,0 +1,668 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use tokio::time::timeout;
+
+use crate::{
+    BindOpts, ConnectOpts, ConnectTarget, DialSource, DialStrategy, DialedStream, Dialer,
+    DialerOpts, Error, HolePunchOpts, TcpListener, tcp_connect, tcp_connect_happy_eyeballs,
+    tcp_hole_punch,
+};
+
+use super::happy_eyeballs::interleave;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn closed_port_addr(ip: std::net::IpAddr) -> SocketAddr {
+    let l = std::net::TcpListener::bind((ip, 0)).unwrap();
+    l.local_addr().unwrap()
+}
+
+/// A listener with a full accept queue: further SYNs are dropped and connects hang.
+///
+/// Linux only: other systems answer with RST (Windows) or handle the backlog differently.
+#[cfg(target_os = "linux")]
+pub(crate) struct Blackhole {
+    _listener: socket2::Socket,
+    _queued: std::net::TcpStream,
+    pub addr: SocketAddr,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn blackhole() -> Blackhole {
+    let listener =
+        socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+    listener
+        .bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())
+        .unwrap();
+    listener.listen(0).unwrap();
+    let addr = listener.local_addr().unwrap().as_socket().unwrap();
+    let queued = std::net::TcpStream::connect(addr).unwrap();
+    Blackhole {
+        _listener: listener,
+        _queued: queued,
+        addr,
+    }
+}
+
+#[test]
+fn test_interleave() {
+    let v6 = |p| SocketAddr::from((Ipv6Addr::LOCALHOST, p));
+    let v4 = |p| SocketAddr::from((Ipv4Addr::LOCALHOST, p));
+    assert_eq!(
+        interleave(vec![v6(1), v6(2), v6(3), v4(4), v4(5)]),
+        vec![v6(1), v4(4), v6(2), v4(5), v6(3)]
+    );
+    assert_eq!(
+        interleave(vec![v4(1), v6(2), v4(3)]),
+        vec![v4(1), v6(2), v4(3)]
+    );
+    assert_eq!(interleave(vec![]), vec![]);
+}
+
+#[tokio::test]
+async fn test_happy_eyeballs_skips_refused() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let addrs = [
+        closed_port_addr(Ipv6Addr::LOCALHOST.into()),
+        listener.bind_addr(),
+    ];
+    let (_stream, addr) = timeout(
+        TIMEOUT,
+        tcp_connect_happy_eyeballs(&addrs[..], ConnectOpts::default()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(addr, listener.bind_addr());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_happy_eyeballs_staggers_past_blackhole() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let blackhole = blackhole();
+    let addrs = [blackhole.addr, listener.bind_addr()];
+    let start = Instant::now();
+    let (_stream, addr) = timeout(
+        TIMEOUT,
+        tcp_connect_happy_eyeballs(&addrs[..], ConnectOpts::default()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(addr, listener.bind_addr());
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_happy_eyeballs_hostname() {
+    let listener =
+        TcpListener::bind_tcp((Ipv6Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap();
+    let host = format!("localhost:{}", listener.bind_addr().port());
+    let (_stream, addr) = timeout(
+        TIMEOUT,
+        tcp_connect_happy_eyeballs(host.as_str(), ConnectOpts::default()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(addr.ip().is_loopback());
+    assert_eq!(addr.port(), listener.bind_addr().port());
+}
+
+#[tokio::test]
+async fn test_happy_eyeballs_all_fail() {
+    let addrs = [
+        closed_port_addr(Ipv6Addr::LOCALHOST.into()),
+        closed_port_addr(Ipv4Addr::LOCALHOST.into()),
+    ];
+    let res = timeout(
+        TIMEOUT,
+        tcp_connect_happy_eyeballs(&addrs[..], ConnectOpts::default()),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(res, Err(Error::Connect(..))), "{res:?}");
+
+    let res = tcp_connect_happy_eyeballs(ConnectTarget::Addrs(&[]), ConnectOpts::default()).await;
+    assert!(matches!(res, Err(Error::NoAddresses)), "{res:?}");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_connect_timeout() {
+    let blackhole = blackhole();
+    let start = Instant::now();
+    let res = timeout(
+        TIMEOUT,
+        tcp_connect(
+            blackhole.addr,
+            ConnectOpts {
+                timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        ),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(res, Err(Error::ConnectTimeout)), "{res:?}");
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_connect_syn_retries() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let stream = tcp_connect(
+        listener.bind_addr(),
+        ConnectOpts {
+            syn_retries: Some(2),
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let syncnt: libc::c_int =
+        crate::sockopt::get(&stream, libc::IPPROTO_TCP, libc::TCP_SYNCNT).unwrap();
+    assert_eq!(syncnt, 2);
+}
+
+// 127.0.0.0/8 is only fully routed to lo on Linux.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_connect_source_addr() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap();
+    let source_ip = Ipv4Addr::new(127, 0, 0, 2);
+    let connect = tcp_connect(
+        (Ipv4Addr::LOCALHOST, listener.bind_addr().port()).into(),
+        ConnectOpts {
+            source_addr: Some(source_ip.into()),
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+    );
+    let (stream, accepted) = tokio::join!(connect, listener.accept());
+    let stream = stream.unwrap();
+    let (_, remote) = accepted.unwrap();
+    assert_eq!(remote.ip(), source_ip);
+    assert_eq!(stream.local_addr().unwrap().ip(), source_ip);
+    assert_ne!(remote.port(), 0);
+
+    let no_port: libc::c_int =
+        crate::sockopt::get(&stream, libc::IPPROTO_IP, libc::IP_BIND_ADDRESS_NO_PORT).unwrap();
+    assert_eq!(no_port, 1);
+}
+
+#[tokio::test]
+async fn test_connect_source_addr_family_mismatch() {
+    let res = tcp_connect(
+        (Ipv4Addr::LOCALHOST, 1).into(),
+        ConnectOpts {
+            source_addr: Some(Ipv6Addr::LOCALHOST.into()),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(
+        matches!(res, Err(Error::SourceAddrFamilyMismatch { .. })),
+        "{res:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_happy_eyeballs_source_addr_filters_family() {
+    let listener =
+        TcpListener::bind_tcp((Ipv6Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap();
+    let port = listener.bind_addr().port();
+    let addrs = [
+        SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+    ];
+    let (_stream, addr) = timeout(
+        TIMEOUT,
+        tcp_connect_happy_eyeballs(
+            &addrs[..],
+            ConnectOpts {
+                source_addr: Some(Ipv4Addr::LOCALHOST.into()),
+                ..Default::default()
+            },
+        ),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(addr.is_ipv4());
+}
+
+/// 127.0.0.2-4, which only Linux routes to lo without configuration.
+fn loopback_sources() -> Vec<DialSource> {
+    [2, 3, 4]
+        .into_iter()
+        .map(|last| DialSource {
+            source_addr: Some(Ipv4Addr::new(127, 0, 0, last).into()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+async fn dial(
+    dialer: &Dialer,
+    listener: &TcpListener,
+    addr: SocketAddr,
+) -> (DialedStream, std::net::IpAddr) {
+    let (stream, accepted) = tokio::join!(
+        dialer.connect(addr, ConnectOpts::default()),
+        listener.accept()
+    );
+    (stream.unwrap(), accepted.unwrap().1.ip())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_dialer_round_robin() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let dialer = Dialer::new(loopback_sources(), DialerOpts::default());
+
+    let mut streams = Vec::new();
+    let mut ips = Vec::new();
+    for _ in 0..4 {
+        let (stream, ip) = dial(&dialer, &listener, listener.bind_addr()).await;
+        ips.push(ip.to_string());
+        streams.push(stream);
+    }
+    assert_eq!(ips, ["127.0.0.2", "127.0.0.3", "127.0.0.4", "127.0.0.2"]);
+    assert_eq!(dialer.live_connections(), vec![2, 1, 1]);
+    drop(streams);
+    assert_eq!(dialer.live_connections(), vec![0, 0, 0]);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_dialer_least_connections() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let dialer = Dialer::new(
+        loopback_sources(),
+        DialerOpts {
+            strategy: DialStrategy::LeastConnections,
+            ..Default::default()
+        },
+    );
+
+    let (s1, _) = dial(&dialer, &listener, listener.bind_addr()).await;
+    let (s2, _) = dial(&dialer, &listener, listener.bind_addr()).await;
+    let (_s3, _) = dial(&dialer, &listener, listener.bind_addr()).await;
+    assert_eq!(dialer.live_connections(), vec![1, 1, 1]);
+    assert_eq!((s1.source(), s2.source()), (0, 1));
+
+    drop(s2);
+    let (s4, ip) = dial(&dialer, &listener, listener.bind_addr()).await;
+    assert_eq!(s4.source(), 1);
+    assert_eq!(ip, Ipv4Addr::new(127, 0, 0, 3));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_dialer_hash_by_peer() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let dialer = Dialer::new(
+        loopback_sources(),
+        DialerOpts {
+            strategy: DialStrategy::HashByPeer,
+            ..Default::default()
+        },
+    );
+
+    let (first, _) = dial(&dialer, &listener, listener.bind_addr()).await;
+    for _ in 0..3 {
+        let (s, _) = dial(&dialer, &listener, listener.bind_addr()).await;
+        assert_eq!(s.source(), first.source());
+    }
+}
+
+#[tokio::test]
+async fn test_dialer_no_source_for_family() {
+    let dialer = Dialer::new(loopback_sources(), DialerOpts::default());
+    let res = dialer
+        .connect((Ipv6Addr::LOCALHOST, 1).into(), ConnectOpts::default())
+        .await;
+    assert!(matches!(res, Err(Error::NoDialSource { .. })));
+    assert_eq!(dialer.live_connections(), vec![0, 0, 0]);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_dialer_dedups_in_progress_dials() {
+    let blackhole = blackhole();
+    let dialer = Dialer::new(vec![], DialerOpts::default());
+    let opts = ConnectOpts {
+        timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let mapped = SocketAddr::from((Ipv4Addr::LOCALHOST.to_ipv6_mapped(), blackhole.addr.port()));
+
+    let mut first = std::pin::pin!(dialer.connect(blackhole.addr, opts));
+    assert!(
+        timeout(Duration::from_millis(50), &mut first)
+            .await
+            .is_err()
+    );
+    let res = dialer.connect(mapped, opts).await;
+    assert!(matches!(res, Err(Error::DialInProgress { .. })), "{res:?}");
+
+    let res = first.await;
+    assert!(matches!(res, Err(Error::ConnectTimeout)), "{res:?}");
+    let res = dialer.connect(blackhole.addr, opts).await;
+    assert!(matches!(res, Err(Error::DialBackoff { .. })), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_dialer_backoff() {
+    let addr = closed_port_addr(Ipv4Addr::LOCALHOST.into());
+    let dialer = Dialer::new(
+        vec![],
+        DialerOpts {
+            backoff: backon::ExponentialBuilder::new()
+                .with_min_delay(Duration::from_millis(100))
+                .with_max_times(2),
+            failure_ttl: Duration::from_millis(500),
+            ..Default::default()
+        },
+    );
+    let opts = ConnectOpts::default();
+
+    let res = dialer.connect(addr, opts).await;
+    assert!(matches!(res, Err(Error::Connect(..))), "{res:?}");
+    let retry_in = match dialer.connect(addr, opts).await {
+        Err(Error::DialBackoff { retry_in, .. }) => retry_in,
+        res => panic!("{res:?}"),
+    };
+    assert!(retry_in <= Duration::from_millis(100));
+
+    tokio::time::sleep(retry_in).await;
+    let res = dialer.connect(addr, opts).await;
+    assert!(matches!(res, Err(Error::Connect(..))), "{res:?}");
+    let retry_in = match dialer.connect(addr, opts).await {
+        Err(Error::DialBackoff { retry_in, .. }) => retry_in,
+        res => panic!("{res:?}"),
+    };
+    assert!(retry_in > Duration::from_millis(100));
+
+    // Out of retries: the address is skipped until the failure expires.
+    tokio::time::sleep(retry_in).await;
+    let res = dialer.connect(addr, opts).await;
+    assert!(matches!(res, Err(Error::Connect(..))), "{res:?}");
+    let retry_in = match dialer.connect(addr, opts).await {
+        Err(Error::DialBackoff { retry_in, .. }) => retry_in,
+        res => panic!("{res:?}"),
+    };
+    assert!(retry_in > Duration::from_millis(400));
+
+    dialer.clear_failure(addr);
+    let res = dialer.connect(addr, opts).await;
+    assert!(matches!(res, Err(Error::Connect(..))), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_dialer_local_error_does_not_back_off() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    // TEST-NET-1, not assigned to any local interface, so binding to it fails.
+    let dialer = Dialer::new(
+        vec![DialSource {
+            source_addr: Some(Ipv4Addr::new(192, 0, 2, 1).into()),
+            ..Default::default()
+        }],
+        DialerOpts {
+            backoff: backon::ExponentialBuilder::new().with_min_delay(Duration::from_secs(10)),
+            ..Default::default()
+        },
+    );
+    for _ in 0..2 {
+        let res = dialer
+            .connect(listener.bind_addr(), ConnectOpts::default())
+            .await;
+        assert!(matches!(res, Err(Error::Bind(..))), "{res:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_dialer_per_destination_limit() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let other =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let dialer = Dialer::new(
+        vec![],
+        DialerOpts {
+            max_per_destination: Some(1),
+            ..Default::default()
+        },
+    );
+
+    let (first, _) = dial(&dialer, &listener, listener.bind_addr()).await;
+    let res = dialer
+        .connect(other.bind_addr(), ConnectOpts::default())
+        .await;
+    assert!(
+        matches!(res, Err(Error::DestinationLimit { limit: 1, .. })),
+        "{res:?}"
+    );
+    assert_eq!(
+        dialer
+            .connections_per_destination()
+            .get(&Ipv4Addr::LOCALHOST.into()),
+        Some(&1)
+    );
+
+    drop(first);
+    assert!(dialer.connections_per_destination().is_empty());
+    let _second = dial(&dialer, &other, other.bind_addr()).await;
+}
+
+#[tokio::test]
+async fn test_dialer_zero_concurrent_dials_is_unlimited() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let dialer = Dialer::new(
+        vec![],
+        DialerOpts {
+            max_concurrent_dials: Some(0),
+            ..Default::default()
+        },
+    );
+    timeout(TIMEOUT, dial(&dialer, &listener, listener.bind_addr()))
+        .await
+        .unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_dialer_max_concurrent_dials() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let blackhole = blackhole();
+    let dialer = Dialer::new(
+        vec![],
+        DialerOpts {
+            max_concurrent_dials: Some(1),
+            ..Default::default()
+        },
+    );
+
+    let start = Instant::now();
+    let hanging = dialer.connect(
+        blackhole.addr,
+        ConnectOpts {
+            timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        },
+    );
+    let waiting = async {
+        // Make sure the hanging dial takes the slot first.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (stream, _) = dial(&dialer, &listener, listener.bind_addr()).await;
+        (stream, start.elapsed())
+    };
+    let (hanging, (_stream, elapsed)) = timeout(TIMEOUT, async { tokio::join!(hanging, waiting) })
+        .await
+        .unwrap();
+    assert!(matches!(hanging, Err(Error::ConnectTimeout)));
+    assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+}
+
+fn reuseport_listener(addr: SocketAddr) -> TcpListener {
+    let opts = BindOpts {
+        reuseport: true,
+        ..Default::default()
+    };
+    TcpListener::bind_tcp(addr, opts).unwrap()
+}
+
+async fn assert_same_connection(mut a: tokio::net::TcpStream, mut b: tokio::net::TcpStream) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    assert_eq!(a.local_addr().unwrap(), b.peer_addr().unwrap());
+    a.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    b.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn test_hole_punch() {
+    let opts = HolePunchOpts {
+        retry_interval: Duration::from_millis(50),
+        ..Default::default()
+    };
+    for (a, b) in [
+        (Ipv4Addr::LOCALHOST.into(), Ipv4Addr::LOCALHOST.into()),
+        (Ipv6Addr::UNSPECIFIED.into(), Ipv4Addr::LOCALHOST.into()),
+        (Ipv6Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()),
+    ] {
+        let a: std::net::IpAddr = a;
+        let b: std::net::IpAddr = b;
+        let la = reuseport_listener((a, 0).into());
+        let lb = reuseport_listener((b, 0).into());
+        let addr_a = SocketAddr::new(
+            if a.is_unspecified() { b } else { a },
+            la.bind_addr().port(),
+        );
+        let (ra, rb) = timeout(
+            TIMEOUT,
+            futures::future::join(
+                tcp_hole_punch(&la, lb.bind_addr(), opts, |_, _| {
+                    panic!("unexpected accept")
+                }),
+                tcp_hole_punch(&lb, addr_a, opts, |_, _| panic!("unexpected accept")),
+            ),
+        )
+        .await
+        .unwrap();
+        let (sa, peer_a) = ra.unwrap();
+        let (sb, peer_b) = rb.unwrap();
+        assert_eq!(peer_a, lb.bind_addr());
+        assert_eq!(peer_b, addr_a);
+        assert!(
+            peer_b.is_ipv4() == b.is_ipv4(),
+            "{peer_b:?} should be canonical"
+        );
+        assert_same_connection(sa, sb).await;
+    }
+}
+
+#[tokio::test]
+async fn test_hole_punch_passes_other_accepts() {
+    let listener = reuseport_listener((Ipv4Addr::LOCALHOST, 0).into());
+    let peer = reuseport_listener((Ipv4Addr::LOCALHOST, 0).into());
+    let other = tokio::net::TcpStream::connect(listener.bind_addr())
+        .await
+        .unwrap();
+
+    let mut others = Vec::new();
+    let opts = HolePunchOpts {
+        retry_interval: Duration::from_millis(50),
+        ..Default::default()
+    };
+    // The peer only listens here, so we connect to it normally.
+    let ((stream, _), (accepted, _)) = timeout(TIMEOUT, async {
+        futures::future::join(
+            tcp_hole_punch(&listener, peer.bind_addr(), opts, |s, addr| {
+                others.push((s, addr))
+            }),
+            peer.accept(),
+        )
+        .await
+    })
+    .await
+    .map(|(a, b)| (a.unwrap(), b.unwrap()))
+    .unwrap();
+    assert_eq!(others.len(), 1);
+    assert_eq!(others[0].1, other.local_addr().unwrap());
+    assert_same_connection(stream, accepted).await;
+}
+
+#[tokio::test]
+async fn test_hole_punch_timeout() {
+    let listener = reuseport_listener((Ipv4Addr::LOCALHOST, 0).into());
+    let opts = HolePunchOpts {
+        window: Duration::from_millis(300),
+        retry_interval: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let res = tcp_hole_punch(
+        &listener,
+        closed_port_addr(Ipv4Addr::LOCALHOST.into()),
+        opts,
+        |_, _| {},
+    )
+    .await;
+    assert!(matches!(res, Err(Error::ConnectTimeout)), "{res:?}");
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn test_hole_punch_requires_reuseport() {
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let peer = reuseport_listener((Ipv4Addr::LOCALHOST, 0).into());
+    let res = timeout(
+        TIMEOUT,
+        tcp_hole_punch(&listener, peer.bind_addr(), Default::default(), |_, _| {}),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(res, Err(Error::Bind(..))), "{res:?}");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_hole_punch_bad_option_is_fatal() {
+    let listener = reuseport_listener((Ipv4Addr::LOCALHOST, 0).into());
+    let peer = reuseport_listener((Ipv4Addr::LOCALHOST, 0).into());
+    let opts = HolePunchOpts {
+        connect: ConnectOpts {
+            tcp_congestion: Some("no-such-algorithm"),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let res = timeout(
+        TIMEOUT,
+        tcp_hole_punch(&listener, peer.bind_addr(), opts, |_, _| {}),
+    )
+    .await
+    .unwrap();
+    assert!(
+        matches!(res, Err(Error::TcpCongestionUnavailable { .. })),
+        "{res:?}"
+    );
+}
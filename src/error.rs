@@ -20,6 +20,8 @@ pub enum Error {
     LocalBindAddrMismatch,
     #[error("error listening")]
     Listen(std::io::Error),
+    #[error("error accepting: {0:#}")]
+    Accept(std::io::Error),
     #[error("error calling tokio from_std")]
     TokioFromStd(std::io::Error),
     #[error("did not join any multicast groups")]
@@ -52,6 +54,163 @@ pub enum Error {
     BindDeviceSetDeviceError(std::io::Error),
     #[error("error connecting: {0:#}")]
     Connect(std::io::Error),
+    #[error("error setting path MTU discovery mode: {0:#}")]
+    MtuDiscover(std::io::Error),
+    #[error("path MTU discovery is not supported on your OS")]
+    MtuDiscoverNotSupported,
+    #[error("error querying path MTU: {0:#}")]
+    PathMtu(std::io::Error),
+    #[error("message of {size} bytes does not fit into path MTU {mtu}")]
+    MessageTooLarge { size: usize, mtu: u32 },
+    #[error("error enabling received TTL reporting: {0:#}")]
+    SetRecvTtl(std::io::Error),
+    #[error("reuseport group must contain at least one socket")]
+    ReuseportGroupEmpty,
+    #[error("sockets in a reuseport group were bound to different addresses")]
+    ReuseportGroupAddrMismatch,
+    #[error("error attaching SO_REUSEPORT BPF program: {0:#}")]
+    AttachReuseportCbpf(std::io::Error),
+    #[error("port {port} is shared with {sockets} other socket(s)")]
+    PortShared { port: u16, sockets: usize },
+    #[error("error checking for sockets sharing our port: {0:#}")]
+    ReuseportGuardCheck(std::io::Error),
+    #[error("checking for sockets sharing our port is not supported on your OS")]
+    ReuseportGuardNotSupported,
+    #[error("error setting SO_RXQ_OVFL: {0:#}")]
+    SetRxqOvfl(std::io::Error),
+    #[error("error getting socket stats: {0:#}")]
+    SocketStats(std::io::Error),
+    #[error("error setting SO_RCVBUF: {0:#}")]
+    SetRecvBufferSize(std::io::Error),
+    #[error("error resolving host: {0:#}")]
+    Resolve(std::io::Error),
+    #[error("no addresses to connect to")]
+    NoAddresses,
+    #[error("timed out connecting")]
+    ConnectTimeout,
+    #[error("error setting TCP_SYNCNT: {0:#}")]
+    SynRetries(std::io::Error),
+    #[error("setting SYN retries is not supported on your OS")]
+    SynRetriesNotSupported,
+    #[error("source address {source_addr} can't be used to connect to {addr}")]
+    SourceAddrFamilyMismatch {
+        source_addr: std::net::IpAddr,
+        addr: std::net::SocketAddr,
+    },
+    #[error("error setting IP_BIND_ADDRESS_NO_PORT: {0:#}")]
+    BindAddressNoPort(std::io::Error),
+    #[error("no dial source can reach {addr}")]
+    NoDialSource { addr: std::net::SocketAddr },
+    #[error("{addr} failed recently, retry in {retry_in:?}")]
+    DialBackoff {
+        addr: std::net::SocketAddr,
+        retry_in: std::time::Duration,
+    },
+    #[error("already dialing {addr}")]
+    DialInProgress { addr: std::net::SocketAddr },
+    #[error("already {limit} connections to {ip}")]
+    DestinationLimit { ip: std::net::IpAddr, limit: usize },
+    #[error("error talking to SOCKS5 proxy: {0:#}")]
+    Socks5(std::io::Error),
+    #[error("invalid reply from SOCKS5 proxy")]
+    Socks5InvalidReply,
+    #[error("SOCKS5 proxy accepts none of the offered auth methods")]
+    Socks5NoAcceptableAuth,
+    #[error("SOCKS5 proxy rejected username or password")]
+    Socks5AuthFailed,
+    #[error("SOCKS5 username and password must be at most 255 bytes")]
+    Socks5CredentialsTooLong,
+    #[error("hostname is too long for SOCKS5")]
+    Socks5HostnameTooLong,
+    #[error("SOCKS5 proxy: general failure")]
+    Socks5GeneralFailure,
+    #[error("SOCKS5 proxy: connection not allowed by ruleset")]
+    Socks5NotAllowed,
+    #[error("SOCKS5 proxy: network unreachable")]
+    Socks5NetworkUnreachable,
+    #[error("SOCKS5 proxy: host unreachable")]
+    Socks5HostUnreachable,
+    #[error("SOCKS5 proxy: connection refused")]
+    Socks5ConnectionRefused,
+    #[error("SOCKS5 proxy: TTL expired")]
+    Socks5TtlExpired,
+    #[error("SOCKS5 proxy: command not supported")]
+    Socks5CommandNotSupported,
+    #[error("SOCKS5 proxy: address type not supported")]
+    Socks5AddressTypeNotSupported,
+    #[error("SOCKS5 proxy: unknown reply code {0}")]
+    Socks5UnknownReply(u8),
+    #[error("only SOCKS5 proxies can relay UDP")]
+    ProxyUdpNotSupported,
+    #[error("error talking to HTTP proxy: {0:#}")]
+    HttpProxy(std::io::Error),
+    #[error("invalid response from HTTP proxy")]
+    HttpProxyInvalidResponse,
+    #[error("HTTP proxy refused to connect: {status_line}")]
+    HttpProxyStatus { status_line: String },
+    #[error("invalid no-proxy rule {0:?}")]
+    NoProxyInvalid(String),
+    #[error("error enabling TCP Fast Open: {0:#}")]
+    FastOpen(std::io::Error),
+    #[error("TCP Fast Open is not supported on your OS")]
+    FastOpenNotSupported,
+    #[error("error getting TCP_INFO: {0:#}")]
+    TcpInfo(std::io::Error),
+    #[error("error setting SO_MARK: {0:#}")]
+    Fwmark(std::io::Error),
+    #[error("setting SO_MARK requires CAP_NET_ADMIN")]
+    FwmarkPermissionDenied,
+    #[error("SO_MARK is not supported on your OS")]
+    FwmarkNotSupported,
+    #[error("error setting TCP_CONGESTION: {0:#}")]
+    TcpCongestion(std::io::Error),
+    #[error("TCP congestion control {name:?} is not available, available: {available:?}")]
+    TcpCongestionUnavailable {
+        name: String,
+        available: Vec<String>,
+    },
+    #[error(
+        "TCP congestion control {name:?} is not in net.ipv4.tcp_allowed_congestion_control and requires CAP_NET_ADMIN"
+    )]
+    TcpCongestionNotAllowed { name: String },
+    #[error("setting TCP congestion control is not supported on your OS")]
+    TcpCongestionNotSupported,
+    #[error("error setting SO_MAX_PACING_RATE: {0:#}")]
+    MaxPacingRate(std::io::Error),
+    #[error("error enabling SO_TXTIME: {0:#}")]
+    TxTime(std::io::Error),
+    #[error("error enabling SO_ZEROCOPY: {0:#}")]
+    Zerocopy(std::io::Error),
+    #[error("error sending STUN request: {0:#}")]
+    Stun(std::io::Error),
+    #[error("no response from STUN server {server}")]
+    StunTimeout { server: std::net::SocketAddr },
+    #[error("invalid STUN response")]
+    StunInvalidResponse,
+    #[error("STUN error response {code}: {reason}")]
+    StunErrorResponse { code: u16, reason: String },
+    #[error("STUN server did not report its alternate address (OTHER-ADDRESS)")]
+    StunNoOtherAddress,
+    #[error("STUN response failed the MESSAGE-INTEGRITY check")]
+    StunIntegrity,
+    #[error("no TURN allocation")]
+    TurnNotAllocated,
+    #[error("all TURN channel numbers are in use")]
+    TurnNoFreeChannel,
+    #[error("error talking to port mapping gateway: {0:#}")]
+    PortMapping(std::io::Error),
+    #[error("no response from port mapping gateway {gateway}")]
+    PortMappingTimeout { gateway: std::net::SocketAddr },
+    #[error("invalid response from port mapping gateway")]
+    PortMappingInvalidResponse,
+    #[error("NAT-PMP gateway returned result code {0}")]
+    NatPmpResult(u16),
+    #[error("PCP gateway returned result code {0}")]
+    PcpResult(u8),
+    #[error("gateway doesn't support PCP")]
+    PcpUnsupportedVersion,
+    #[error("NAT-PMP only supports IPv4 gateways")]
+    NatPmpIpv6,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
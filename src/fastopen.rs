@@ -0,0 +1,132 @@
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+use std::net::SocketAddr;
+
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tracing::{debug, trace};
+
+use crate::{ConnectOpts, Error, tcp_connect};
+
+/// Whether the data passed to [`tcp_connect_with_data`] went out in the SYN.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FastOpenStatus {
+    /// The data was sent in the SYN and accepted by the server.
+    DataInSyn,
+    /// The data was sent after a regular handshake, e.g. because we had no cookie for the server
+    /// yet, the server rejected it, or fast open wasn't requested.
+    Fallback,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn set_listener_qlen(sock: &socket2::Socket, qlen: u32) -> crate::Result<()> {
+    trace!(qlen, "setting TCP_FASTOPEN");
+    crate::sockopt::set(
+        sock,
+        libc::IPPROTO_TCP,
+        libc::TCP_FASTOPEN,
+        qlen.min(libc::c_int::MAX as u32) as libc::c_int,
+    )
+    .map_err(Error::FastOpen)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_listener_qlen(_sock: &socket2::Socket, _qlen: u32) -> crate::Result<()> {
+    Err(Error::FastOpenNotSupported)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn set_connect(sock: &socket2::Socket) -> crate::Result<()> {
+    crate::sockopt::set(
+        sock,
+        libc::IPPROTO_TCP,
+        libc::TCP_FASTOPEN_CONNECT,
+        1 as libc::c_int,
+    )
+    .map_err(Error::FastOpen)
+}
+
+// Connect normally, tcp_connect_with_data reports FastOpenStatus::Fallback.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_connect(_sock: &socket2::Socket) -> crate::Result<()> {
+    debug!("TCP fast open is not supported on this OS, connecting normally");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn syn_data_acked(stream: &TcpStream) -> std::io::Result<bool> {
+    Ok(crate::tcp_info::get(stream)?.syn_data_acked)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn syn_data_acked(_stream: &TcpStream) -> std::io::Result<bool> {
+    Ok(false)
+}
+
+async fn write_in_syn(stream: &TcpStream, addr: SocketAddr, data: &[u8]) -> crate::Result<()> {
+    let mut written = 0;
+    while written < data.len() {
+        match stream.try_write(&data[written..]) {
+            Ok(n) => written += n,
+            // Without a cookie the kernel sends a plain SYN and the data has to wait for the
+            // handshake.
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.raw_os_error() == Some(libc::EINPROGRESS) =>
+            {
+                stream.writable().await.map_err(Error::Connect)?;
+            }
+            Err(e) => return Err(Error::Connect(e)),
+        }
+    }
+    // Wait for the handshake to finish, so that the kernel knows if the data in SYN was acked.
+    stream.writable().await.map_err(Error::Connect)?;
+    if let Some(e) = stream.take_error().map_err(Error::Connect)? {
+        return Err(Error::Connect(e));
+    }
+    trace!(?addr, written, "fast open: handshake finished");
+    Ok(())
+}
+
+/// Connect to `addr` and send `data`. With [`ConnectOpts::fast_open`], `data` is sent in the SYN
+/// if possible (TCP_FASTOPEN_CONNECT). `timeout` covers sending `data` too.
+///
+/// Fast open isn't attempted through a proxy, with empty `data` or on other systems than Linux; the
+/// data is then written after a regular handshake and [`FastOpenStatus::Fallback`] is returned.
+pub async fn tcp_connect_with_data(
+    addr: SocketAddr,
+    opts: ConnectOpts<'_>,
+    data: &[u8],
+) -> crate::Result<(TcpStream, FastOpenStatus)> {
+    let fast_open =
+        cfg!(target_os = "linux") && opts.fast_open && opts.proxy.is_none() && !data.is_empty();
+    let connect_and_write = async {
+        let mut stream = tcp_connect(
+            addr,
+            ConnectOpts {
+                fast_open,
+                timeout: None,
+                ..opts
+            },
+        )
+        .await?;
+        if !fast_open {
+            stream.write_all(data).await.map_err(Error::Connect)?;
+            return Ok((stream, FastOpenStatus::Fallback));
+        }
+        write_in_syn(&stream, addr, data).await?;
+        let status = if syn_data_acked(&stream).map_err(Error::TcpInfo)? {
+            FastOpenStatus::DataInSyn
+        } else {
+            FastOpenStatus::Fallback
+        };
+        debug!(?addr, ?status, "fast open: connected");
+        Ok((stream, status))
+    };
+    match opts.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, connect_and_write)
+            .await
+            .map_err(|_| Error::ConnectTimeout)?,
+        None => connect_and_write.await,
+    }
+}
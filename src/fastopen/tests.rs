@@ -0,0 +1,116 @@
+use std::{net::Ipv4Addr, time::Duration};
+
+use tokio::io::AsyncReadExt;
+
+use crate::{BindOpts, ConnectOpts, Error, FastOpenStatus, TcpListener, tcp_connect_with_data};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const SYSCTL: &str = "/proc/sys/net/ipv4/tcp_fastopen";
+
+/// Client and server fast open (net.ipv4.tcp_fastopen |= 3), enabled for the duration of a test.
+/// The previous value is restored on drop.
+struct FastOpenSysctl {
+    restore: Option<String>,
+}
+
+impl FastOpenSysctl {
+    /// None if fast open is off and we can't turn it on.
+    fn enable() -> Option<Self> {
+        let old = std::fs::read_to_string(SYSCTL).ok()?;
+        let value: u32 = old.trim().parse().unwrap();
+        if value & 3 == 3 {
+            return Some(Self { restore: None });
+        }
+        std::fs::write(SYSCTL, (value | 3).to_string()).ok()?;
+        Some(Self { restore: Some(old) })
+    }
+}
+
+impl Drop for FastOpenSysctl {
+    fn drop(&mut self) {
+        if let Some(old) = self.restore.take() {
+            let _ = std::fs::write(SYSCTL, old.trim());
+        }
+    }
+}
+
+fn listener() -> TcpListener {
+    TcpListener::bind_tcp(
+        (Ipv4Addr::LOCALHOST, 0).into(),
+        BindOpts {
+            tcp_fastopen: Some(16),
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+async fn connect_and_check(listener: &TcpListener, fast_open: bool) -> FastOpenStatus {
+    let connect = tcp_connect_with_data(
+        listener.bind_addr(),
+        ConnectOpts {
+            fast_open,
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+        b"hello",
+    );
+    let (res, accepted) = tokio::join!(connect, listener.accept());
+    let (_stream, status) = res.unwrap();
+    let (mut accepted, _) = accepted.unwrap();
+    let mut buf = [0u8; 5];
+    accepted.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    status
+}
+
+#[tokio::test]
+async fn test_listener_fastopen_qlen() {
+    let listener = listener();
+    let qlen: libc::c_int =
+        crate::sockopt::get(listener.socket(), libc::IPPROTO_TCP, libc::TCP_FASTOPEN).unwrap();
+    assert_eq!(qlen, 16);
+}
+
+#[tokio::test]
+async fn test_connect_with_data_fast_open() {
+    let sysctl = FastOpenSysctl::enable();
+    let listener = listener();
+
+    // The first connection may only fetch a cookie, unless one is cached from an earlier run.
+    connect_and_check(&listener, true).await;
+    let status = connect_and_check(&listener, true).await;
+    if sysctl.is_some() {
+        assert_eq!(status, FastOpenStatus::DataInSyn);
+    } else {
+        println!("could not enable {SYSCTL}, not checking if data went in SYN");
+    }
+}
+
+#[tokio::test]
+async fn test_connect_with_data_without_fast_open() {
+    let listener = listener();
+    assert_eq!(
+        connect_and_check(&listener, false).await,
+        FastOpenStatus::Fallback
+    );
+}
+
+#[tokio::test]
+async fn test_connect_with_data_refused() {
+    let addr = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let res = tcp_connect_with_data(
+        addr,
+        ConnectOpts {
+            fast_open: true,
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+        b"hello",
+    )
+    .await;
+    assert!(matches!(res, Err(Error::Connect(..))), "{res:?}");
+}
@@ -0,0 +1,22 @@
+#[cfg(all(test, target_os = "linux"))]
+pub(crate) mod tests;
+
+use crate::Error;
+
+/// Set SO_MARK, used by policy routing (`ip rule fwmark`). Needs CAP_NET_ADMIN.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_fwmark(sock: &socket2::Socket, mark: u32) -> crate::Result<()> {
+    tracing::trace!(mark, "setting SO_MARK");
+    sock.set_mark(mark).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            Error::FwmarkPermissionDenied
+        } else {
+            Error::Fwmark(e)
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_fwmark(_sock: &socket2::Socket, _mark: u32) -> crate::Result<()> {
+    Err(Error::FwmarkNotSupported)
+}
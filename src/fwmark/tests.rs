@@ -0,0 +1,69 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::{BindOpts, ConnectOpts, Error, TcpListener, TcpStreamExt, UdpSocket, tcp_connect};
+
+/// Returns None if we lack CAP_NET_ADMIN, after checking that the error says so.
+pub(crate) fn bind_marked_listener(mark: u32) -> Option<TcpListener> {
+    match TcpListener::bind_tcp(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        BindOpts {
+            fwmark: Some(mark),
+            ..Default::default()
+        },
+    ) {
+        Ok(l) => Some(l),
+        Err(Error::FwmarkPermissionDenied) => {
+            println!("no CAP_NET_ADMIN, skipping");
+            None
+        }
+        Err(e) => panic!("{e:#}"),
+    }
+}
+
+#[tokio::test]
+async fn test_fwmark_tcp() {
+    let Some(listener) = bind_marked_listener(42) else {
+        return;
+    };
+    assert_eq!(
+        socket2::SockRef::from(listener.socket()).mark().unwrap(),
+        42
+    );
+
+    let connect = tcp_connect(
+        (Ipv4Addr::LOCALHOST, listener.bind_addr().port()).into(),
+        ConnectOpts {
+            fwmark: Some(7),
+            ..Default::default()
+        },
+    );
+    let (stream, accepted) = tokio::join!(connect, listener.accept());
+    assert_eq!(stream.unwrap().fwmark().unwrap(), 7);
+    // Accepted streams inherit the listener's mark.
+    assert_eq!(accepted.unwrap().0.fwmark().unwrap(), 42);
+}
+
+#[tokio::test]
+async fn test_fwmark_udp() {
+    if bind_marked_listener(1).is_none() {
+        return;
+    }
+    let sock = UdpSocket::bind_udp(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        BindOpts {
+            fwmark: Some(42),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(socket2::SockRef::from(sock.socket()).mark().unwrap(), 42);
+    assert!(sock.path_mtu((Ipv4Addr::LOCALHOST, 1).into()).is_ok());
+
+    // The path MTU is looked up on the route the mark selects.
+    let probe = crate::pmtu::path_mtu_probe(
+        socket2::SockRef::from(sock.socket()),
+        (Ipv4Addr::LOCALHOST, 1).into(),
+    )
+    .unwrap();
+    assert_eq!(probe.mark().unwrap(), 42);
+}
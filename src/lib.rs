@@ -1,11 +1,39 @@
 #[cfg(test)]
 mod tests;
 
+#[cfg(target_os = "linux")]
+mod ancillary;
 mod bind_device;
+#[cfg(target_os = "linux")]
+mod cmsg;
+mod congestion;
 mod connect;
 mod error;
+mod fastopen;
+mod fwmark;
+mod mptcp;
 mod multicast;
+#[cfg(target_os = "linux")]
+mod pacing;
+mod pmtu;
+mod port_guard;
+mod port_mapping;
+mod proxy;
+#[cfg(target_os = "linux")]
+mod reuseport;
+#[cfg(target_os = "linux")]
+mod sendfile;
+#[cfg(target_os = "linux")]
+mod sockopt;
+mod stream;
+mod stun;
+mod tcp_info;
 mod traits;
+mod turn;
+#[cfg(target_os = "linux")]
+mod udp_stats;
+#[cfg(target_os = "linux")]
+mod zerocopy;
 pub use error::{Error, Result};
 
 use crate::socket::MaybeDualstackSocket;
@@ -15,11 +43,37 @@ pub mod socket;
 
 pub type TcpListener = MaybeDualstackSocket<tokio::net::TcpListener>;
 pub type UdpSocket = MaybeDualstackSocket<tokio::net::UdpSocket>;
+#[cfg(target_os = "linux")]
+pub use ancillary::RecvMeta;
 pub use bind_device::BindDevice;
-pub use connect::{ConnectOpts, tcp_connect};
+pub use congestion::available_tcp_congestion;
+pub use connect::{
+    CONNECTION_ATTEMPT_DELAY, ConnectOpts, ConnectTarget, DialSource, DialStrategy, DialedStream,
+    Dialer, DialerOpts, HolePunchOpts, tcp_connect, tcp_connect_happy_eyeballs, tcp_connect_host,
+    tcp_hole_punch,
+};
+pub use fastopen::{FastOpenStatus, tcp_connect_with_data};
 pub use multicast::{MulticastOpts, MulticastUdpSocket};
+#[cfg(target_os = "linux")]
+pub use pacing::{TxTime, TxTimeClock};
+pub use pmtu::MtuDiscover;
+pub use port_guard::ReuseportGuard;
+pub use port_mapping::{PORT_MAPPING_PORT, PortMapping, PortMappingOpts, PortMappingProtocol};
+pub use proxy::{NoProxy, Proxy, ProxyAuth, Socks5UdpSocket};
+#[cfg(target_os = "linux")]
+pub use reuseport::{ReuseportSteering, ReuseportUdpGroup};
+#[cfg(target_os = "linux")]
+pub use sendfile::tcp_send_file;
 pub use socket::BindOpts;
+pub use stream::TcpStreamExt;
+pub use stun::{NatMapping, StunBinding, StunClient, StunOpts};
+pub use tcp_info::{TcpInfo, TcpInfoDelta, TcpInfoSampler};
 pub use traits::PollSendToVectored;
+pub use turn::{TurnClient, TurnOpts};
+#[cfg(target_os = "linux")]
+pub use udp_stats::{RcvbufAutotuner, UdpSocketStats};
+#[cfg(target_os = "linux")]
+pub use zerocopy::{ZerocopyBuf, ZerocopySender};
 
 #[cfg(feature = "axum")]
 pub use socket::axum::WrappedSocketAddr;
@@ -0,0 +1,51 @@
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(target_os = "linux")]
+use tracing::debug;
+
+use crate::Error;
+
+/// Create a TCP socket, or an MPTCP one if `mptcp` is set and the kernel supports it.
+#[cfg(target_os = "linux")]
+pub(crate) fn tcp_socket(domain: Domain, mptcp: bool) -> crate::Result<Socket> {
+    if mptcp {
+        match Socket::new(domain, Type::STREAM, Some(Protocol::MPTCP)) {
+            Ok(sock) => return Ok(sock),
+            // Kernels before 5.6 don't know IPPROTO_MPTCP and return EINVAL.
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::ENOPROTOOPT | libc::EPROTONOSUPPORT | libc::EINVAL)
+                ) =>
+            {
+                debug!("MPTCP unavailable, falling back to TCP: {e:#}");
+            }
+            Err(e) => return Err(Error::SocketNew(e)),
+        }
+    }
+    Socket::new(domain, Type::STREAM, Some(Protocol::TCP)).map_err(Error::SocketNew)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn tcp_socket(domain: Domain, _mptcp: bool) -> crate::Result<Socket> {
+    Socket::new(domain, Type::STREAM, Some(Protocol::TCP)).map_err(Error::SocketNew)
+}
+
+/// Whether MPTCP is in use, i.e. the socket is MPTCP and hasn't fallen back to TCP.
+#[cfg(target_os = "linux")]
+pub(crate) fn is_mptcp(sock: &impl std::os::fd::AsFd) -> std::io::Result<bool> {
+    // From linux/tcp.h, since 5.16. MPTCP sockets that fell back pass it on to the TCP subflow,
+    // which reports 0.
+    const TCP_IS_MPTCP: libc::c_int = 43;
+    match crate::sockopt::get::<libc::c_int>(sock, libc::IPPROTO_TCP, TCP_IS_MPTCP) {
+        Ok(value) => Ok(value != 0),
+        Err(e) if e.raw_os_error() == Some(libc::ENOPROTOOPT) => {
+            let protocol: libc::c_int =
+                crate::sockopt::get(sock, libc::SOL_SOCKET, libc::SO_PROTOCOL)?;
+            Ok(protocol == libc::IPPROTO_MPTCP)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,74 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::{BindOpts, ConnectOpts, TcpListener, TcpStreamExt, tcp_connect};
+
+fn mptcp_available() -> bool {
+    let available =
+        std::fs::read_to_string("/proc/sys/net/mptcp/enabled").is_ok_and(|v| v.trim() == "1");
+    if !available {
+        println!("MPTCP is not enabled, skipping");
+    }
+    available
+}
+
+fn mptcp_listener(mptcp: bool) -> TcpListener {
+    TcpListener::bind_tcp(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        BindOpts {
+            mptcp,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+async fn connect(
+    listener: &TcpListener,
+    mptcp: bool,
+) -> (tokio::net::TcpStream, tokio::net::TcpStream) {
+    let addr = (Ipv4Addr::LOCALHOST, listener.bind_addr().port()).into();
+    let connect = tcp_connect(
+        addr,
+        ConnectOpts {
+            mptcp,
+            ..Default::default()
+        },
+    );
+    let (stream, accepted) = tokio::join!(connect, listener.accept());
+    let (accepted, remote) = accepted.unwrap();
+    // Dualstack MPTCP listeners still report canonical IPv4 addresses.
+    assert_eq!(remote.ip(), Ipv4Addr::LOCALHOST);
+    (stream.unwrap(), accepted)
+}
+
+#[tokio::test]
+async fn test_mptcp_dualstack() {
+    if !mptcp_available() {
+        return;
+    }
+    let listener = mptcp_listener(true);
+    assert!(listener.is_mptcp());
+    assert!(listener.is_dualstack());
+
+    let (stream, accepted) = connect(&listener, true).await;
+    assert!(stream.is_mptcp().unwrap());
+    assert!(accepted.is_mptcp().unwrap());
+}
+
+#[tokio::test]
+async fn test_mptcp_falls_back_with_plain_tcp_peer() {
+    if !mptcp_available() {
+        return;
+    }
+    let listener = mptcp_listener(false);
+    assert!(!listener.is_mptcp());
+
+    let (stream, accepted) = connect(&listener, true).await;
+    assert!(!stream.is_mptcp().unwrap());
+    assert!(!accepted.is_mptcp().unwrap());
+
+    let listener = mptcp_listener(true);
+    let (stream, accepted) = connect(&listener, false).await;
+    assert!(!stream.is_mptcp().unwrap());
+    assert!(!accepted.is_mptcp().unwrap());
+}
@@ -33,6 +33,34 @@ impl MulticastUdpSocket {
         ipv6_site_local_addr: SocketAddrV6,
         ipv6_link_local_addr: Option<SocketAddrV6>,
         bind_device: Option<&BindDevice>,
+        fwmark: Option<u32>,
+    ) -> crate::Result<Self> {
+        Self::new_with_opts(
+            bind_addr,
+            ipv4_mcast_addr,
+            ipv6_site_local_addr,
+            ipv6_link_local_addr,
+            BindOpts {
+                device: bind_device,
+                fwmark,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    /// Like [`new`](Self::new), with the rest of [`BindOpts`], e.g. `reuseport_guard` to find out
+    /// if other processes listen on the same port.
+    ///
+    /// `opts.request_dualstack` and `opts.reuseport` are always enabled, as other processes
+    /// usually listen on the same multicast port. With `opts.device`, only that interface is
+    /// used.
+    pub async fn new_with_opts(
+        bind_addr: SocketAddr,
+        ipv4_mcast_addr: SocketAddrV4,
+        ipv6_site_local_addr: SocketAddrV6,
+        ipv6_link_local_addr: Option<SocketAddrV6>,
+        opts: BindOpts<'_>,
     ) -> crate::Result<Self> {
         if let Some(ll) = ipv6_link_local_addr
             && !ll.ip().is_link_local_mcast()
@@ -45,7 +73,7 @@ impl MulticastUdpSocket {
         let nics = network_interface::NetworkInterface::show()
             .into_iter()
             .flatten()
-            .filter(|nic| bind_device.is_none_or(|bd| bd.index().get() == nic.index))
+            .filter(|nic| opts.device.is_none_or(|bd| bd.index().get() == nic.index))
             .collect::<Vec<_>>();
         if nics.is_empty() {
             return Err(Error::NoNics);
@@ -53,7 +81,7 @@ impl MulticastUdpSocket {
         let opts = BindOpts {
             request_dualstack: true,
             reuseport: true,
-            device: bind_device,
+            ..opts
         };
         let sock = UdpSocket::bind_udp(bind_addr, opts)?;
         let sock = Self {
@@ -75,6 +103,19 @@ impl MulticastUdpSocket {
         self.sock.recv_from(buf).await
     }
 
+    #[cfg(target_os = "linux")]
+    pub async fn recv_from_with_meta(
+        &self,
+        buf: &mut [u8],
+    ) -> std::io::Result<(usize, SocketAddr, crate::RecvMeta)> {
+        self.sock.recv_from_with_meta(buf).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_recv_ttl(&self, enable: bool) -> crate::Result<()> {
+        self.sock.set_recv_ttl(enable)
+    }
+
     pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
         // Ensure the multicast option is erased before sending
         poll_fn(|cx| {
@@ -202,6 +243,26 @@ impl MulticastUdpSocket {
         &self,
         buf: &[u8],
         opts: &MulticastOpts,
+    ) -> crate::Result<usize> {
+        self.send_multicast_msg_impl(buf, opts, None).await
+    }
+
+    /// Same as [`send_multicast_msg`](Self::send_multicast_msg), but with the given TTL / hop limit.
+    #[cfg(target_os = "linux")]
+    pub async fn send_multicast_msg_with_ttl(
+        &self,
+        buf: &[u8],
+        opts: &MulticastOpts,
+        ttl: u8,
+    ) -> crate::Result<usize> {
+        self.send_multicast_msg_impl(buf, opts, Some(ttl)).await
+    }
+
+    async fn send_multicast_msg_impl(
+        &self,
+        buf: &[u8],
+        opts: &MulticastOpts,
+        ttl: Option<u8>,
     ) -> crate::Result<usize> {
         // This is .poll_fn() so that we call .set_multicast_if_*() immediately before sending a packet.
         // If it's repolled it'll get called again just before the send.
@@ -228,9 +289,14 @@ impl MulticastUdpSocket {
                 _ => return Poll::Ready(Err(Error::SendMulticastMsgProtocolMismatch)),
             }
 
-            self.sock
-                .poll_send_to(cx, buf, opts.mcast_addr)
-                .map_err(Error::Send)
+            let res = match ttl {
+                #[cfg(target_os = "linux")]
+                Some(ttl) => self
+                    .sock
+                    .poll_send_to_with_ttl(cx, buf, opts.mcast_addr, ttl),
+                _ => self.sock.poll_send_to(cx, buf, opts.mcast_addr),
+            };
+            res.map_err(Error::Send)
         })
         .await
     }
@@ -238,6 +304,26 @@ impl MulticastUdpSocket {
     pub async fn try_send_mcast_everywhere(
         &self,
         get_payload: &impl Fn(&MulticastOpts) -> Option<String>,
+    ) {
+        self.try_send_mcast_everywhere_impl(get_payload, None).await
+    }
+
+    /// Same as [`try_send_mcast_everywhere`](Self::try_send_mcast_everywhere), but with the given
+    /// TTL / hop limit, e.g. to keep probes on the LAN.
+    #[cfg(target_os = "linux")]
+    pub async fn try_send_mcast_everywhere_with_ttl(
+        &self,
+        get_payload: &impl Fn(&MulticastOpts) -> Option<String>,
+        ttl: u8,
+    ) {
+        self.try_send_mcast_everywhere_impl(get_payload, Some(ttl))
+            .await
+    }
+
+    async fn try_send_mcast_everywhere_impl(
+        &self,
+        get_payload: &impl Fn(&MulticastOpts) -> Option<String>,
+        ttl: Option<u8>,
     ) {
         let bind_is_ipv6 = self.sock.bind_addr().is_ipv6();
 
@@ -273,7 +359,10 @@ impl MulticastUdpSocket {
         let futs = send_specs.into_iter().filter_map(|opts| {
             let payload = get_payload(&opts)?;
             let fut = async move {
-                match self.send_multicast_msg(payload.as_bytes(), &opts).await {
+                match self
+                    .send_multicast_msg_impl(payload.as_bytes(), &opts, ttl)
+                    .await
+                {
                     Ok(sz) => trace!(?opts, size=sz, payload=?payload, "sent"),
                     Err(e) => {
                         debug!(?opts, payload=?payload, "error sending: {e:#}")
@@ -7,7 +7,7 @@ use bstr::BStr;
 use tokio::time::timeout;
 use tracing::trace;
 
-use crate::{BindDevice, MulticastUdpSocket};
+use crate::{BindDevice, BindOpts, MulticastUdpSocket};
 
 async fn bind_mcast_sock(port: u16, bd_name: Option<&str>) -> MulticastUdpSocket {
     let bd = bd_name.map(|name| BindDevice::new_from_name(name).unwrap());
@@ -22,6 +22,7 @@ async fn bind_mcast_sock(port: u16, bd_name: Option<&str>) -> MulticastUdpSocket
             0,
         )),
         bd.as_ref(),
+        None,
     )
     .await
     .unwrap()
@@ -184,3 +185,84 @@ async fn test_mcast_bind_device() {
     assert_eq!(sz, 5);
     assert_eq!(&buf, b"hello");
 }
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_v4_received_with_ttl() {
+    setup_test_logging();
+    let sock = bind_mcast_sock(1906, None).await;
+    sock.set_recv_ttl(true).unwrap();
+
+    sock.try_send_mcast_everywhere_with_ttl(
+        &|opts| {
+            if opts.iface_ip().is_ipv4() {
+                Some("hello".into())
+            } else {
+                None
+            }
+        },
+        1,
+    )
+    .await;
+
+    let mut buf = [0u8; 5];
+    let (sz, addr, meta) = timeout(
+        Duration::from_millis(100),
+        sock.recv_from_with_meta(&mut buf),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(sz, 5);
+    assert!(addr.is_ipv4(), "{addr:?} expected v4");
+    assert_eq!(meta.ttl, Some(1));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_fwmark() {
+    if crate::fwmark::tests::bind_marked_listener(1).is_none() {
+        return;
+    }
+    let port = 1907;
+    let sock = MulticastUdpSocket::new(
+        (Ipv6Addr::UNSPECIFIED, port).into(),
+        SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), port),
+        SocketAddrV6::new(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0xc), port, 0, 0),
+        None,
+        None,
+        Some(42),
+    )
+    .await
+    .unwrap();
+    let mark = socket2::SockRef::from(sock.sock.socket()).mark().unwrap();
+    assert_eq!(mark, 42);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_reuseport_guard() {
+    let port = 1908;
+    let new = |guard| {
+        MulticastUdpSocket::new_with_opts(
+            (Ipv6Addr::UNSPECIFIED, port).into(),
+            SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), port),
+            SocketAddrV6::new(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0xc), port, 0, 0),
+            None,
+            BindOpts {
+                reuseport_guard: guard,
+                ..Default::default()
+            },
+        )
+    };
+    use crate::ReuseportGuard;
+
+    let _first = new(ReuseportGuard::Deny).await.unwrap();
+    let res = new(ReuseportGuard::Deny).await;
+    assert!(
+        matches!(res, Err(crate::Error::PortShared { .. })),
+        "{:?}",
+        res.err()
+    );
+    new(ReuseportGuard::Off).await.unwrap();
+}
@@ -0,0 +1,131 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    future::poll_fn,
+    io::IoSlice,
+    net::SocketAddr,
+    os::fd::AsFd,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use crate::{
+    Error,
+    cmsg::{self, CmsgBuf},
+};
+
+pub(crate) fn set_max_pacing_rate(sock: &impl AsFd, rate: Option<u64>) -> std::io::Result<()> {
+    // ~0 means unlimited.
+    let rate = rate.unwrap_or(u64::MAX);
+    tracing::trace!(rate, "setting SO_MAX_PACING_RATE");
+    crate::sockopt::set(sock, libc::SOL_SOCKET, libc::SO_MAX_PACING_RATE, rate)
+}
+
+pub(crate) fn max_pacing_rate(sock: &impl AsFd) -> std::io::Result<Option<u64>> {
+    let rate: u64 = crate::sockopt::get(sock, libc::SOL_SOCKET, libc::SO_MAX_PACING_RATE)?;
+    Ok(Some(rate).filter(|r| *r != u64::MAX))
+}
+
+/// The clock SO_TXTIME departure times are in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxTimeClock {
+    /// Required by the `fq` qdisc.
+    Monotonic,
+    /// Commonly used with the `etf` qdisc.
+    Tai,
+}
+
+impl TxTimeClock {
+    fn clockid(self) -> libc::clockid_t {
+        match self {
+            TxTimeClock::Monotonic => libc::CLOCK_MONOTONIC,
+            TxTimeClock::Tai => libc::CLOCK_TAI,
+        }
+    }
+
+    fn now_nanos(self) -> u64 {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // Can't fail for these clocks.
+        unsafe { libc::clock_gettime(self.clockid(), &mut ts) };
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+}
+
+/// Departure time of a datagram sent with [`UdpSocket::send_to_at`](crate::UdpSocket::send_to_at).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TxTime(u64);
+
+impl TxTime {
+    /// `clock` must be the one passed to [`enable_txtime`](crate::UdpSocket::enable_txtime).
+    pub fn from_instant(at: Instant, clock: TxTimeClock) -> Self {
+        let now = Instant::now();
+        let now_nanos = clock.now_nanos();
+        let nanos = if at >= now {
+            now_nanos.saturating_add((at - now).as_nanos() as u64)
+        } else {
+            now_nanos.saturating_sub((now - at).as_nanos() as u64)
+        };
+        Self(nanos)
+    }
+
+    /// Nanoseconds in the clock passed to [`enable_txtime`](crate::UdpSocket::enable_txtime).
+    pub fn from_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    pub fn as_nanos(&self) -> u64 {
+        self.0
+    }
+}
+
+impl crate::UdpSocket {
+    /// Limit the rate the kernel sends at, in bytes per second (SO_MAX_PACING_RATE). None means
+    /// unlimited. Only enforced with the `fq` qdisc. Can be changed at any time.
+    pub fn set_max_pacing_rate(&self, rate: Option<u64>) -> crate::Result<()> {
+        set_max_pacing_rate(self.socket(), rate).map_err(Error::MaxPacingRate)
+    }
+
+    pub fn max_pacing_rate(&self) -> crate::Result<Option<u64>> {
+        max_pacing_rate(self.socket()).map_err(Error::MaxPacingRate)
+    }
+
+    /// Enable SO_TXTIME, so that [`send_to_at`](Self::send_to_at) can schedule departure times.
+    /// They are enforced by the `fq` or `etf` qdisc, other qdiscs send right away.
+    pub fn enable_txtime(&self, clock: TxTimeClock) -> crate::Result<()> {
+        let txtime = libc::sock_txtime {
+            clockid: clock.clockid(),
+            flags: 0,
+        };
+        crate::sockopt::set(self.socket(), libc::SOL_SOCKET, libc::SO_TXTIME, txtime)
+            .map_err(Error::TxTime)
+    }
+
+    /// Send a datagram that leaves no earlier than `at` (SCM_TXTIME). Needs
+    /// [`enable_txtime`](Self::enable_txtime).
+    pub async fn send_to_at(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+        at: TxTime,
+    ) -> std::io::Result<usize> {
+        poll_fn(|cx| self.poll_send_to_at(cx, buf, target, at)).await
+    }
+
+    pub fn poll_send_to_at(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+        at: TxTime,
+    ) -> Poll<std::io::Result<usize>> {
+        let target = self.convert_addr_for_send(target);
+        // SCM_TXTIME is a socket-level option, so it's the same for mapped IPv4 destinations.
+        let mut cmsg = CmsgBuf::default();
+        cmsg.push(libc::SOL_SOCKET, libc::SCM_TXTIME, at.0);
+        cmsg::poll_sendmsg(self.socket(), cx, &[IoSlice::new(buf)], target, &cmsg)
+    }
+}
@@ -0,0 +1,94 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use tokio::time::timeout;
+
+use crate::{BindOpts, TcpListener, TcpStreamExt, TxTime, TxTimeClock, UdpSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+fn bind(addr: SocketAddr) -> UdpSocket {
+    UdpSocket::bind_udp(addr, BindOpts::default()).unwrap()
+}
+
+#[tokio::test]
+async fn test_udp_max_pacing_rate() {
+    let sock = bind((Ipv6Addr::UNSPECIFIED, 0).into());
+    assert_eq!(sock.max_pacing_rate().unwrap(), None);
+    sock.set_max_pacing_rate(Some(1_000_000)).unwrap();
+    assert_eq!(sock.max_pacing_rate().unwrap(), Some(1_000_000));
+    sock.set_max_pacing_rate(Some(5_000)).unwrap();
+    assert_eq!(sock.max_pacing_rate().unwrap(), Some(5_000));
+    sock.set_max_pacing_rate(None).unwrap();
+    assert_eq!(sock.max_pacing_rate().unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_tcp_max_pacing_rate() {
+    let listener =
+        TcpListener::bind_tcp((Ipv6Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap();
+    let port = listener.bind_addr().port();
+    let stream = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .await
+        .unwrap();
+    let (accepted, _) = listener.accept().await.unwrap();
+
+    for stream in [&stream, &accepted] {
+        assert_eq!(stream.max_pacing_rate().unwrap(), None);
+        stream.set_max_pacing_rate(Some(125_000)).unwrap();
+        assert_eq!(stream.max_pacing_rate().unwrap(), Some(125_000));
+        stream.set_max_pacing_rate(None).unwrap();
+        assert_eq!(stream.max_pacing_rate().unwrap(), None);
+    }
+}
+
+#[test]
+fn test_txtime_from_instant() {
+    let clock = TxTimeClock::Monotonic;
+    let now = TxTime::from_instant(Instant::now(), clock);
+    let later = TxTime::from_instant(Instant::now() + Duration::from_millis(100), clock);
+    let diff = later.as_nanos() - now.as_nanos();
+    assert!(
+        (Duration::from_millis(100).as_nanos() as u64
+            ..Duration::from_millis(150).as_nanos() as u64)
+            .contains(&diff),
+        "{diff}"
+    );
+}
+
+async fn assert_received(sender: &UdpSocket, receiver: &UdpSocket, to: SocketAddr, at: TxTime) {
+    sender.send_to_at(b"hello", to, at).await.unwrap();
+    let mut buf = [0u8; 16];
+    let (sz, _) = timeout(TIMEOUT, receiver.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..sz], b"hello", "to={to:?}");
+}
+
+#[tokio::test]
+async fn test_send_to_at_dualstack() {
+    let sender = bind((Ipv6Addr::UNSPECIFIED, 0).into());
+    assert!(sender.is_dualstack());
+    let clock = TxTimeClock::Monotonic;
+    sender.enable_txtime(clock).unwrap();
+
+    let v4 = bind((Ipv4Addr::LOCALHOST, 0).into());
+    let v6 = bind((Ipv6Addr::LOCALHOST, 0).into());
+    let mapped: SocketAddr = (Ipv4Addr::LOCALHOST.to_ipv6_mapped(), v4.bind_addr().port()).into();
+
+    let at = TxTime::from_instant(Instant::now(), clock);
+    assert_received(&sender, &v4, v4.bind_addr(), at).await;
+    assert_received(&sender, &v4, mapped, at).await;
+    assert_received(&sender, &v6, v6.bind_addr(), at).await;
+}
+
+#[tokio::test]
+async fn test_send_to_at_ipv4() {
+    let sender = bind((Ipv4Addr::LOCALHOST, 0).into());
+    let clock = TxTimeClock::Tai;
+    sender.enable_txtime(clock).unwrap();
+    let receiver = bind((Ipv4Addr::LOCALHOST, 0).into());
+    let at = TxTime::from_instant(Instant::now(), clock);
+    assert_received(&sender, &receiver, receiver.bind_addr(), at).await;
+}
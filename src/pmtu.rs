@@ -0,0 +1,171 @@
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+use std::net::SocketAddr;
+
+use socket2::SockRef;
+use tracing::{debug, trace};
+
+use crate::{Error, socket::SocketAddrKind};
+
+/// Path MTU discovery mode (IP_MTU_DISCOVER / IPV6_MTU_DISCOVER).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MtuDiscover {
+    /// Never set the DF bit, fragment locally if needed.
+    Dont,
+    /// Use per-route hints, fragment if the packet is bigger than the path MTU.
+    Want,
+    /// Always set the DF bit, fail sends bigger than the known path MTU.
+    Do,
+    /// Set the DF bit, but ignore the path MTU. Used for probing (DPLPMTUD).
+    Probe,
+}
+
+#[cfg(target_os = "linux")]
+impl MtuDiscover {
+    fn as_ipv4(&self) -> libc::c_int {
+        match self {
+            MtuDiscover::Dont => libc::IP_PMTUDISC_DONT,
+            MtuDiscover::Want => libc::IP_PMTUDISC_WANT,
+            MtuDiscover::Do => libc::IP_PMTUDISC_DO,
+            MtuDiscover::Probe => libc::IP_PMTUDISC_PROBE,
+        }
+    }
+
+    fn as_ipv6(&self) -> libc::c_int {
+        match self {
+            MtuDiscover::Dont => libc::IPV6_PMTUDISC_DONT,
+            MtuDiscover::Want => libc::IPV6_PMTUDISC_WANT,
+            MtuDiscover::Do => libc::IPV6_PMTUDISC_DO,
+            MtuDiscover::Probe => libc::IPV6_PMTUDISC_PROBE,
+        }
+    }
+}
+
+// Dualstack sockets send IPv4 through the IP_* options, so both need to be set.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_mtu_discover(
+    sock: &socket2::Socket,
+    mode: MtuDiscover,
+    addr_kind: SocketAddrKind,
+) -> crate::Result<()> {
+    let (set_v4, set_v6) = match addr_kind {
+        SocketAddrKind::V4(..) => (true, false),
+        SocketAddrKind::V6 { is_dualstack, .. } => (is_dualstack, true),
+    };
+    if set_v4 {
+        trace!(?mode, "setting IP_MTU_DISCOVER");
+        crate::sockopt::set(
+            sock,
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            mode.as_ipv4(),
+        )
+        .map_err(Error::MtuDiscover)?;
+    }
+    if set_v6 {
+        trace!(?mode, "setting IPV6_MTU_DISCOVER");
+        crate::sockopt::set(
+            sock,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            mode.as_ipv6(),
+        )
+        .map_err(Error::MtuDiscover)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_mtu_discover(
+    _sock: &socket2::Socket,
+    _mode: MtuDiscover,
+    _addr_kind: SocketAddrKind,
+) -> crate::Result<()> {
+    Err(Error::MtuDiscoverNotSupported)
+}
+
+// IP_MTU only works on connected sockets, so we connect a throwaway socket to the peer and ask it.
+// The route cache (and thus the discovered MTU) is shared with the main socket.
+#[cfg(target_os = "linux")]
+fn query_path_mtu(sock: SockRef<'_>, peer: SocketAddr) -> std::io::Result<u32> {
+    use crate::addr::TryToV4;
+
+    let peer = peer.try_to_ipv4();
+    let probe = path_mtu_probe(sock, peer)?;
+    let mtu: libc::c_int = if peer.is_ipv4() {
+        crate::sockopt::get(&probe, libc::IPPROTO_IP, libc::IP_MTU)?
+    } else {
+        crate::sockopt::get(&probe, libc::IPPROTO_IPV6, libc::IPV6_MTU)?
+    };
+    Ok(mtu as u32)
+}
+
+/// A socket connected to `peer` that routes like `sock`.
+#[cfg(target_os = "linux")]
+pub(crate) fn path_mtu_probe(
+    sock: SockRef<'_>,
+    peer: SocketAddr,
+) -> std::io::Result<socket2::Socket> {
+    let probe = socket2::Socket::new(
+        socket2::Domain::for_address(peer),
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    if let Some(device) = sock.device()? {
+        probe.bind_device(Some(&device))?;
+    }
+    // The mark may select a different route.
+    let mark = sock.mark()?;
+    if mark != 0 {
+        probe.set_mark(mark)?;
+    }
+    probe.connect(&peer.into())?;
+    Ok(probe)
+}
+
+impl crate::UdpSocket {
+    /// Query the kernel's current path MTU estimate towards `peer`.
+    #[cfg(target_os = "linux")]
+    pub fn path_mtu(&self, peer: SocketAddr) -> crate::Result<u32> {
+        query_path_mtu(SockRef::from(self.socket()), peer).map_err(Error::PathMtu)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn path_mtu(&self, _peer: SocketAddr) -> crate::Result<u32> {
+        Err(Error::MtuDiscoverNotSupported)
+    }
+
+    /// Same as [`send_to`](Self::send_to), but if the datagram doesn't fit into the path MTU
+    /// returns [`Error::MessageTooLarge`] with the current MTU.
+    pub async fn send_to_checked(&self, buf: &[u8], target: SocketAddr) -> crate::Result<usize> {
+        match self.send_to(buf, target).await {
+            Ok(sz) => Ok(sz),
+            Err(e) if is_emsgsize(&e) => match self.path_mtu(target) {
+                Ok(mtu) => {
+                    debug!(?target, size = buf.len(), mtu, "datagram too large");
+                    Err(Error::MessageTooLarge {
+                        size: buf.len(),
+                        mtu,
+                    })
+                }
+                Err(mtu_err) => {
+                    debug!(?target, "error querying path MTU: {mtu_err:#}");
+                    Err(Error::Send(e))
+                }
+            },
+            Err(e) => Err(Error::Send(e)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_emsgsize(e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+#[cfg(windows)]
+fn is_emsgsize(e: &std::io::Error) -> bool {
+    // WSAEMSGSIZE
+    e.raw_os_error() == Some(10040)
+}
@@ -0,0 +1,69 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{BindOpts, Error, MtuDiscover, UdpSocket, bind_device::tests::find_localhost_name};
+
+// The kernel caps IPv4 MTU at 65535 even though loopback's MTU is usually 65536.
+fn loopback_mtu(is_v4: bool) -> u32 {
+    let name = find_localhost_name();
+    let mtu: u32 = std::fs::read_to_string(format!("/sys/class/net/{name}/mtu"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    if is_v4 { mtu.min(u16::MAX as u32) } else { mtu }
+}
+
+fn bind_dualstack(mode: MtuDiscover) -> UdpSocket {
+    let sock = UdpSocket::bind_udp(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        BindOpts {
+            mtu_discover: Some(mode),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(sock.is_dualstack());
+    sock
+}
+
+#[tokio::test]
+async fn test_mtu_discover_set_on_both_families() {
+    let sock = bind_dualstack(MtuDiscover::Do);
+    let v4: libc::c_int =
+        crate::sockopt::get(sock.socket(), libc::IPPROTO_IP, libc::IP_MTU_DISCOVER).unwrap();
+    let v6: libc::c_int =
+        crate::sockopt::get(sock.socket(), libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER).unwrap();
+    assert_eq!(v4, libc::IP_PMTUDISC_DO);
+    assert_eq!(v6, libc::IPV6_PMTUDISC_DO);
+}
+
+#[tokio::test]
+async fn test_path_mtu_localhost() {
+    let sock = bind_dualstack(MtuDiscover::Do);
+    for peer in [
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 1234)),
+        SocketAddr::from((Ipv6Addr::LOCALHOST, 1234)),
+    ] {
+        let mtu = loopback_mtu(peer.is_ipv4());
+        assert_eq!(sock.path_mtu(peer).unwrap(), mtu, "{peer:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_send_too_large() {
+    let sock = bind_dualstack(MtuDiscover::Do);
+    let target = SocketAddr::from((Ipv4Addr::LOCALHOST, sock.bind_addr().port()));
+    let buf = vec![0u8; loopback_mtu(true) as usize + 1];
+    match sock.send_to_checked(&buf, target).await {
+        Err(Error::MessageTooLarge { size, mtu }) => {
+            assert_eq!(size, buf.len());
+            assert_eq!(mtu, loopback_mtu(true));
+        }
+        other => panic!("expected MessageTooLarge, got {other:?}"),
+    }
+
+    assert_eq!(
+        sock.send_to_checked(&buf[..1000], target).await.unwrap(),
+        1000
+    );
+}
@@ -0,0 +1,194 @@
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+use std::net::SocketAddr;
+
+#[cfg(target_os = "linux")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use tracing::debug;
+
+use crate::Error;
+
+/// What to do if, after binding, other sockets turn out to share our port.
+///
+/// With SO_REUSEPORT, any other process of the same user can bind the same port and the kernel
+/// will silently distribute our traffic between the sockets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReuseportGuard {
+    #[default]
+    Off,
+    /// Log a warning.
+    Warn,
+    /// Fail the bind with [`Error::PortShared`].
+    Deny,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq)]
+struct ProcNetEntry {
+    addr: SocketAddr,
+    state: u8,
+    inode: u64,
+}
+
+// Addresses are printed as the raw (network order) words in host order, ports in host order.
+#[cfg(target_os = "linux")]
+fn parse_proc_net_addr(s: &str) -> Option<SocketAddr> {
+    let (ip, port) = s.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let word = |i: usize| {
+        u32::from_str_radix(ip.get(i * 8..(i + 1) * 8)?, 16)
+            .ok()
+            .map(u32::to_ne_bytes)
+    };
+    let ip: IpAddr = match ip.len() {
+        8 => Ipv4Addr::from(word(0)?).into(),
+        32 => {
+            let mut octets = [0u8; 16];
+            for (i, chunk) in octets.chunks_exact_mut(4).enumerate() {
+                chunk.copy_from_slice(&word(i)?);
+            }
+            Ipv6Addr::from(octets).into()
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(target_os = "linux")]
+fn parse_proc_net(contents: &str) -> impl Iterator<Item = ProcNetEntry> + '_ {
+    contents.lines().skip(1).filter_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        Some(ProcNetEntry {
+            addr: parse_proc_net_addr(fields.get(1)?)?,
+            state: u8::from_str_radix(fields.get(3)?, 16).ok()?,
+            inode: fields.get(9)?.parse().ok()?,
+        })
+    })
+}
+
+// Whether a socket bound to `other` would receive traffic destined to `ours`.
+#[cfg(target_os = "linux")]
+fn addrs_overlap(ours: SocketAddr, is_dualstack: bool, other: SocketAddr) -> bool {
+    use crate::addr::TryToV4;
+
+    if ours.port() != other.port() {
+        return false;
+    }
+    let (ours_ip, other_ip) = (ours.try_to_ipv4().ip(), other.try_to_ipv4().ip());
+    if ours_ip == other_ip {
+        return true;
+    }
+    match (ours_ip, other_ip) {
+        (IpAddr::V6(o), IpAddr::V4(_)) if o.is_unspecified() => is_dualstack,
+        (IpAddr::V4(_), IpAddr::V6(o)) if o.is_unspecified() => true,
+        (o, other) => {
+            (o.is_unspecified() || other.is_unspecified()) && o.is_ipv4() == other.is_ipv4()
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn count_sharing_sockets(
+    sock: &socket2::Socket,
+    local_addr: SocketAddr,
+    is_dualstack: bool,
+    is_udp: bool,
+) -> std::io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    const TCP_LISTEN: u8 = 0x0a;
+
+    let own_inode = {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(sock.as_raw_fd(), &mut stat) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        stat.st_ino
+    };
+
+    let files: &[&str] = if is_udp {
+        &["/proc/net/udp", "/proc/net/udp6"]
+    } else {
+        &["/proc/net/tcp", "/proc/net/tcp6"]
+    };
+
+    let mut count = 0;
+    for file in files {
+        let contents = match std::fs::read_to_string(file) {
+            Ok(c) => c,
+            // e.g. IPv6 disabled
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        count += parse_proc_net(&contents)
+            .filter(|e| e.inode != own_inode && e.inode != 0)
+            .filter(|e| is_udp || e.state == TCP_LISTEN)
+            .filter(|e| addrs_overlap(local_addr, is_dualstack, e.addr))
+            .count();
+    }
+    Ok(count)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn check(
+    guard: ReuseportGuard,
+    sock: &socket2::Socket,
+    local_addr: SocketAddr,
+    is_dualstack: bool,
+    is_udp: bool,
+) -> crate::Result<()> {
+    if guard == ReuseportGuard::Off {
+        return Ok(());
+    }
+    let count = count_sharing_sockets(sock, local_addr, is_dualstack, is_udp)
+        .map_err(Error::ReuseportGuardCheck)?;
+    if count == 0 {
+        return Ok(());
+    }
+    let port = local_addr.port();
+    match guard {
+        ReuseportGuard::Off => Ok(()),
+        ReuseportGuard::Warn => {
+            tracing::warn!(
+                ?local_addr,
+                sockets = count,
+                "other sockets are bound to the same port and may receive our traffic"
+            );
+            Ok(())
+        }
+        ReuseportGuard::Deny => {
+            debug!(
+                ?local_addr,
+                sockets = count,
+                "port is shared with other sockets"
+            );
+            Err(Error::PortShared {
+                port,
+                sockets: count,
+            })
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn check(
+    guard: ReuseportGuard,
+    _sock: &socket2::Socket,
+    local_addr: SocketAddr,
+    _is_dualstack: bool,
+    _is_udp: bool,
+) -> crate::Result<()> {
+    match guard {
+        ReuseportGuard::Off => Ok(()),
+        ReuseportGuard::Warn => {
+            debug!(
+                ?local_addr,
+                "checking for shared ports is not supported on your OS"
+            );
+            Ok(())
+        }
+        ReuseportGuard::Deny => Err(Error::ReuseportGuardNotSupported),
+    }
+}
@@ -0,0 +1,76 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{BindOpts, Error, ReuseportGuard, TcpListener, UdpSocket};
+
+use super::parse_proc_net_addr;
+
+fn opts(guard: ReuseportGuard) -> BindOpts<'static> {
+    BindOpts {
+        reuseport: true,
+        reuseport_guard: guard,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_parse_proc_net_addr() {
+    assert_eq!(
+        parse_proc_net_addr("0100007F:1F90"),
+        Some(SocketAddr::from((Ipv4Addr::LOCALHOST, 8080)))
+    );
+    assert_eq!(
+        parse_proc_net_addr("00000000000000000000000001000000:0016"),
+        Some(SocketAddr::from((Ipv6Addr::LOCALHOST, 22)))
+    );
+    assert_eq!(parse_proc_net_addr("garbage"), None);
+}
+
+#[tokio::test]
+async fn test_udp_port_not_shared() {
+    UdpSocket::bind_udp(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        opts(ReuseportGuard::Deny),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_udp_port_shared() {
+    let first =
+        UdpSocket::bind_udp((Ipv6Addr::UNSPECIFIED, 0).into(), opts(ReuseportGuard::Off)).unwrap();
+    let port = first.bind_addr().port();
+
+    // A v4 socket on the same port steals part of the dualstack socket's traffic too.
+    match UdpSocket::bind_udp(
+        (Ipv4Addr::LOCALHOST, port).into(),
+        opts(ReuseportGuard::Deny),
+    ) {
+        Err(Error::PortShared { port: p, sockets }) => {
+            assert_eq!(p, port);
+            assert_eq!(sockets, 1);
+        }
+        Err(e) => panic!("expected PortShared, got {e:?}"),
+        Ok(_) => panic!("expected PortShared"),
+    }
+
+    UdpSocket::bind_udp(
+        (Ipv6Addr::UNSPECIFIED, port).into(),
+        opts(ReuseportGuard::Warn),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_port_shared() {
+    let first =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), opts(ReuseportGuard::Deny)).unwrap();
+    let port = first.bind_addr().port();
+
+    assert!(matches!(
+        TcpListener::bind_tcp(
+            (Ipv4Addr::LOCALHOST, port).into(),
+            opts(ReuseportGuard::Deny)
+        ),
+        Err(Error::PortShared { .. })
+    ));
+}
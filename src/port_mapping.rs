@@ -0,0 +1,489 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+use tracing::{debug, trace};
+
+use crate::{Error, TcpListener, UdpSocket, addr::TryToV4, stun::codec::new_transaction_id};
+
+/// The port NAT-PMP and PCP gateways listen on.
+pub const PORT_MAPPING_PORT: u16 = 5351;
+
+const NAT_PMP_VERSION: u8 = 0;
+const NAT_PMP_OP_EXTERNAL_ADDR: u8 = 0;
+const NAT_PMP_OP_MAP_UDP: u8 = 1;
+const NAT_PMP_OP_MAP_TCP: u8 = 2;
+const PCP_VERSION: u8 = 2;
+const PCP_OP_MAP: u8 = 1;
+const PCP_RESPONSE_BIT: u8 = 0x80;
+const PCP_MAP_RESPONSE_LEN: usize = 60;
+// Same value for NAT-PMP and PCP.
+const RESULT_UNSUPPORTED_VERSION: u16 = 1;
+
+/// With [`PortMappingProtocol::Auto`], how many PCP requests to send before trying NAT-PMP, as
+/// older gateways silently drop PCP.
+const PCP_PROBE_REQUESTS: u32 = 3;
+
+/// When renewing fails, how long to wait at least before trying again.
+const MIN_RENEW_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PortMappingProtocol {
+    /// PCP, falling back to NAT-PMP if the gateway doesn't support it or doesn't answer the first
+    /// few PCP requests.
+    #[default]
+    Auto,
+    /// NAT-PMP (RFC 6886). IPv4 only.
+    NatPmp,
+    /// PCP (RFC 6887). With an IPv6 gateway, opens a firewall pinhole instead of a NAT mapping.
+    Pcp,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PortMappingOpts {
+    pub protocol: PortMappingProtocol,
+    /// Requested lifetime. The gateway may grant a different one, the mapping is renewed
+    /// halfway through whatever was granted.
+    pub lifetime: Duration,
+    /// Preferred external port. Gateways treat it as a hint.
+    pub external_port: Option<u16>,
+    /// Initial retransmission timeout, doubled after each retransmit (RFC 6886 section 3.1).
+    pub rto: Duration,
+    /// How many times to send a request in total.
+    pub max_requests: u32,
+}
+
+impl Default for PortMappingOpts {
+    fn default() -> Self {
+        Self {
+            protocol: PortMappingProtocol::Auto,
+            lifetime: Duration::from_secs(7200),
+            external_port: None,
+            rto: Duration::from_millis(250),
+            max_requests: 9,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transport {
+    Tcp,
+    Udp,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Granted {
+    external_addr: SocketAddr,
+    lifetime: Duration,
+}
+
+struct Client {
+    sock: tokio::net::UdpSocket,
+    gateway: SocketAddr,
+    local_ip: IpAddr,
+    /// Never Auto once the mapping is created.
+    protocol: PortMappingProtocol,
+    transport: Transport,
+    internal_port: u16,
+    nonce: [u8; 12],
+    opts: PortMappingOpts,
+}
+
+fn be16(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes(buf[at..at + 2].try_into().unwrap())
+}
+
+fn be32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn lifetime_secs(lifetime: Duration) -> u32 {
+    lifetime.as_secs().try_into().unwrap_or(u32::MAX)
+}
+
+/// PCP carries IPv4 addresses as IPv4-mapped IPv6.
+fn pcp_addr(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+impl Client {
+    async fn new(
+        gateway: SocketAddr,
+        transport: Transport,
+        internal_port: u16,
+        opts: PortMappingOpts,
+    ) -> crate::Result<Self> {
+        let gateway = gateway.try_to_ipv4();
+        if gateway.is_ipv6() && opts.protocol == PortMappingProtocol::NatPmp {
+            return Err(Error::NatPmpIpv6);
+        }
+        let unspecified: IpAddr = if gateway.is_ipv6() {
+            Ipv6Addr::UNSPECIFIED.into()
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        };
+        let sock = tokio::net::UdpSocket::bind((unspecified, 0))
+            .await
+            .map_err(Error::PortMapping)?;
+        sock.connect(gateway).await.map_err(Error::PortMapping)?;
+        let local_ip = sock.local_addr().map_err(Error::PortMapping)?.ip();
+        Ok(Self {
+            sock,
+            gateway,
+            local_ip,
+            protocol: opts.protocol,
+            transport,
+            internal_port,
+            nonce: new_transaction_id(),
+            opts,
+        })
+    }
+
+    /// Send `req` until `parse` accepts a response.
+    async fn request<T>(
+        &self,
+        req: &[u8],
+        parse: impl Fn(&[u8]) -> Option<crate::Result<T>>,
+    ) -> crate::Result<T> {
+        let mut rto = self.opts.rto;
+        let mut buf = [0u8; 1100];
+        for attempt in 0..self.opts.max_requests {
+            trace!(gateway=?self.gateway, attempt, "sending port mapping request");
+            self.sock.send(req).await.map_err(Error::PortMapping)?;
+            let deadline = Instant::now() + rto;
+            while let Ok(res) = tokio::time::timeout_at(deadline, self.sock.recv(&mut buf)).await {
+                let len = res.map_err(Error::PortMapping)?;
+                match parse(&buf[..len]) {
+                    Some(res) => return res,
+                    None => trace!(len, "ignoring unrelated packet from port mapping gateway"),
+                }
+            }
+            rto = rto.saturating_mul(2);
+        }
+        Err(Error::PortMappingTimeout {
+            gateway: self.gateway,
+        })
+    }
+
+    /// Create, renew (`lifetime` > 0) or delete (`lifetime` == 0) the mapping.
+    async fn map(&self, external_port: Option<u16>, lifetime: Duration) -> crate::Result<Granted> {
+        match self.protocol {
+            PortMappingProtocol::NatPmp => self.nat_pmp_map(external_port, lifetime).await,
+            _ => self.pcp_map(external_port, lifetime).await,
+        }
+    }
+
+    async fn delete(&self) -> crate::Result<()> {
+        self.map(None, Duration::ZERO).await?;
+        debug!(gateway=?self.gateway, port = self.internal_port, "deleted port mapping");
+        Ok(())
+    }
+
+    async fn nat_pmp_external_ip(&self) -> crate::Result<Ipv4Addr> {
+        self.request(&[NAT_PMP_VERSION, NAT_PMP_OP_EXTERNAL_ADDR], |buf| {
+            if buf.len() < 4 || buf[0] != NAT_PMP_VERSION || buf[1] != 128 {
+                return None;
+            }
+            Some(match be16(buf, 2) {
+                0 if buf.len() >= 12 => Ok(Ipv4Addr::from(be32(buf, 8))),
+                0 => Err(Error::PortMappingInvalidResponse),
+                code => Err(Error::NatPmpResult(code)),
+            })
+        })
+        .await
+    }
+
+    async fn nat_pmp_map(
+        &self,
+        external_port: Option<u16>,
+        lifetime: Duration,
+    ) -> crate::Result<Granted> {
+        // Deletes don't need to know the external address.
+        let external_ip = if lifetime.is_zero() {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            self.nat_pmp_external_ip().await?
+        };
+        let op = match self.transport {
+            Transport::Udp => NAT_PMP_OP_MAP_UDP,
+            Transport::Tcp => NAT_PMP_OP_MAP_TCP,
+        };
+        let mut req = [0u8; 12];
+        req[0] = NAT_PMP_VERSION;
+        req[1] = op;
+        req[4..6].copy_from_slice(&self.internal_port.to_be_bytes());
+        req[6..8].copy_from_slice(&external_port.unwrap_or(0).to_be_bytes());
+        req[8..12].copy_from_slice(&lifetime_secs(lifetime).to_be_bytes());
+
+        self.request(&req, |buf| {
+            if buf.len() < 4 || buf[0] != NAT_PMP_VERSION || buf[1] != 128 + op {
+                return None;
+            }
+            let code = be16(buf, 2);
+            if code != 0 {
+                return Some(Err(Error::NatPmpResult(code)));
+            }
+            if buf.len() < 16 {
+                return Some(Err(Error::PortMappingInvalidResponse));
+            }
+            if be16(buf, 8) != self.internal_port {
+                return None;
+            }
+            Some(Ok(Granted {
+                external_addr: (external_ip, be16(buf, 10)).into(),
+                lifetime: Duration::from_secs(be32(buf, 12).into()),
+            }))
+        })
+        .await
+    }
+
+    async fn pcp_map(
+        &self,
+        external_port: Option<u16>,
+        lifetime: Duration,
+    ) -> crate::Result<Granted> {
+        let protocol: u8 = match self.transport {
+            Transport::Udp => 17,
+            Transport::Tcp => 6,
+        };
+        let suggested_ip: IpAddr = if self.gateway.is_ipv6() {
+            Ipv6Addr::UNSPECIFIED.into()
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        };
+        let mut req = [0u8; 60];
+        req[0] = PCP_VERSION;
+        req[1] = PCP_OP_MAP;
+        req[4..8].copy_from_slice(&lifetime_secs(lifetime).to_be_bytes());
+        req[8..24].copy_from_slice(&pcp_addr(self.local_ip));
+        req[24..36].copy_from_slice(&self.nonce);
+        req[36] = protocol;
+        req[40..42].copy_from_slice(&self.internal_port.to_be_bytes());
+        req[42..44].copy_from_slice(&external_port.unwrap_or(0).to_be_bytes());
+        req[44..60].copy_from_slice(&pcp_addr(suggested_ip));
+
+        self.request(&req, |buf| {
+            // A NAT-PMP only gateway answers with its own version (RFC 6887 section 9).
+            if buf.len() >= 4
+                && buf[0] == NAT_PMP_VERSION
+                && be16(buf, 2) == RESULT_UNSUPPORTED_VERSION
+            {
+                return Some(Err(Error::PcpUnsupportedVersion));
+            }
+            if buf.len() < 4 || buf[0] != PCP_VERSION || buf[1] != PCP_RESPONSE_BIT | PCP_OP_MAP {
+                return None;
+            }
+            if buf.len() >= PCP_MAP_RESPONSE_LEN && buf[24..36] != self.nonce {
+                return None;
+            }
+            let code = buf[3];
+            if code as u16 == RESULT_UNSUPPORTED_VERSION {
+                return Some(Err(Error::PcpUnsupportedVersion));
+            }
+            if code != 0 {
+                return Some(Err(Error::PcpResult(code)));
+            }
+            if buf.len() < PCP_MAP_RESPONSE_LEN {
+                return Some(Err(Error::PortMappingInvalidResponse));
+            }
+            let external_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&buf[44..60]).unwrap());
+            Some(Ok(Granted {
+                external_addr: (external_ip.to_canonical(), be16(buf, 42)).into(),
+                lifetime: Duration::from_secs(be32(buf, 4).into()),
+            }))
+        })
+        .await
+    }
+}
+
+/// A port mapping (NAT-PMP or PCP) of a local socket on the gateway.
+///
+/// The mapping is renewed in the background until it's dropped or [deleted](Self::delete).
+/// Dropping it deletes it from the gateway in a background task.
+pub struct PortMapping {
+    client: Arc<Client>,
+    external_addr: watch::Receiver<SocketAddr>,
+    renew: JoinHandle<()>,
+    deleted: bool,
+}
+
+impl std::fmt::Debug for PortMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PortMapping")
+            .field("gateway", &self.client.gateway)
+            .field("protocol", &self.client.protocol)
+            .field("internal_port", &self.client.internal_port)
+            .field("external_addr", &*self.external_addr.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PortMapping {
+    /// Map the port of `listener` on `gateway` (usually the default router on
+    /// [`PORT_MAPPING_PORT`]).
+    pub async fn map_tcp(
+        listener: &TcpListener,
+        gateway: SocketAddr,
+        opts: PortMappingOpts,
+    ) -> crate::Result<Self> {
+        Self::new(gateway, Transport::Tcp, listener.bind_addr().port(), opts).await
+    }
+
+    /// Map the port of `sock` on `gateway` (usually the default router on
+    /// [`PORT_MAPPING_PORT`]).
+    pub async fn map_udp(
+        sock: &UdpSocket,
+        gateway: SocketAddr,
+        opts: PortMappingOpts,
+    ) -> crate::Result<Self> {
+        Self::new(gateway, Transport::Udp, sock.bind_addr().port(), opts).await
+    }
+
+    async fn new(
+        gateway: SocketAddr,
+        transport: Transport,
+        internal_port: u16,
+        opts: PortMappingOpts,
+    ) -> crate::Result<Self> {
+        let mut client = Client::new(gateway, transport, internal_port, opts).await?;
+        // A zero lifetime would delete the mapping.
+        let lifetime = opts.lifetime.max(Duration::from_secs(1));
+        let granted = match opts.protocol {
+            PortMappingProtocol::Auto => {
+                client.protocol = PortMappingProtocol::Pcp;
+                let can_fall_back = client.gateway.is_ipv4();
+                if can_fall_back {
+                    client.opts.max_requests = opts.max_requests.min(PCP_PROBE_REQUESTS);
+                }
+                let res = client.map(opts.external_port, lifetime).await;
+                client.opts.max_requests = opts.max_requests;
+                match res {
+                    Err(e @ (Error::PcpUnsupportedVersion | Error::PortMappingTimeout { .. }))
+                        if can_fall_back =>
+                    {
+                        debug!(gateway=?client.gateway, "PCP failed, trying NAT-PMP: {e:#}");
+                        client.protocol = PortMappingProtocol::NatPmp;
+                        client.map(opts.external_port, lifetime).await?
+                    }
+                    res => res?,
+                }
+            }
+            _ => client.map(opts.external_port, lifetime).await?,
+        };
+        debug!(
+            gateway=?client.gateway,
+            protocol=?client.protocol,
+            ?transport,
+            internal_port,
+            external_addr=?granted.external_addr,
+            lifetime=?granted.lifetime,
+            "created port mapping"
+        );
+
+        let client = Arc::new(client);
+        let (tx, rx) = watch::channel(granted.external_addr);
+        let renew = tokio::spawn(renew_loop(client.clone(), granted, lifetime, tx));
+        Ok(Self {
+            client,
+            external_addr: rx,
+            renew,
+            deleted: false,
+        })
+    }
+
+    /// The address peers can reach the socket on. With an IPv6 PCP gateway, this is the local
+    /// address and port.
+    pub fn external_addr(&self) -> SocketAddr {
+        *self.external_addr.borrow()
+    }
+
+    /// Notified when the external address changes after a renewal, e.g. after the gateway
+    /// got a new address or lost its state.
+    pub fn watch_external_addr(&self) -> watch::Receiver<SocketAddr> {
+        self.external_addr.clone()
+    }
+
+    /// The protocol in use. Never [`PortMappingProtocol::Auto`].
+    pub fn protocol(&self) -> PortMappingProtocol {
+        self.client.protocol
+    }
+
+    pub fn gateway(&self) -> SocketAddr {
+        self.client.gateway
+    }
+
+    /// Delete the mapping from the gateway and wait for it to confirm.
+    pub async fn delete(mut self) -> crate::Result<()> {
+        self.renew.abort();
+        self.deleted = true;
+        self.client.delete().await
+    }
+}
+
+impl Drop for PortMapping {
+    fn drop(&mut self) {
+        self.renew.abort();
+        if self.deleted {
+            return;
+        }
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            debug!(gateway=?self.client.gateway, "no tokio runtime, can't delete port mapping");
+            return;
+        };
+        let client = self.client.clone();
+        handle.spawn(async move {
+            if let Err(e) = client.delete().await {
+                debug!(gateway=?client.gateway, "error deleting port mapping: {e:#}");
+            }
+        });
+    }
+}
+
+async fn renew_loop(
+    client: Arc<Client>,
+    mut granted: Granted,
+    lifetime: Duration,
+    tx: watch::Sender<SocketAddr>,
+) {
+    let mut expires = Instant::now() + granted.lifetime;
+    let mut renew_at = Instant::now() + (granted.lifetime / 2).max(MIN_RENEW_INTERVAL);
+    loop {
+        tokio::time::sleep_until(renew_at).await;
+        // Ask for the same external port, so that peers don't need to learn a new one.
+        let res = client
+            .map(Some(granted.external_addr.port()), lifetime)
+            .await;
+        let now = Instant::now();
+        match res {
+            Ok(renewed) => {
+                if renewed.external_addr != granted.external_addr {
+                    debug!(
+                        gateway=?client.gateway,
+                        old=?granted.external_addr,
+                        new=?renewed.external_addr,
+                        "external address of port mapping changed"
+                    );
+                }
+                trace!(gateway=?client.gateway, lifetime=?renewed.lifetime, "renewed port mapping");
+                tx.send_replace(renewed.external_addr);
+                granted = renewed;
+                expires = now + granted.lifetime;
+                renew_at = now + (granted.lifetime / 2).max(MIN_RENEW_INTERVAL);
+            }
+            Err(e) => {
+                debug!(gateway=?client.gateway, "error renewing port mapping: {e:#}");
+                renew_at =
+                    now + (expires.saturating_duration_since(now) / 2).max(MIN_RENEW_INTERVAL);
+            }
+        }
+    }
+}
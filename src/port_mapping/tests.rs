@@ -0,0 +1,404 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::timeout;
+
+use crate::{
+    BindOpts, Error, PortMapping, PortMappingOpts, PortMappingProtocol, TcpListener, UdpSocket,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const EXTERNAL_IP: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 7);
+
+#[derive(Clone, Copy)]
+struct GatewayOpts {
+    pcp: bool,
+    /// Without PCP, drop PCP requests instead of answering UNSUPP_VERSION.
+    drop_pcp: bool,
+    max_lifetime: u32,
+    /// Result code to fail every mapping request with.
+    fail: u8,
+}
+
+impl Default for GatewayOpts {
+    fn default() -> Self {
+        Self {
+            pcp: true,
+            drop_pcp: false,
+            max_lifetime: 3600,
+            fail: 0,
+        }
+    }
+}
+
+struct GatewayState {
+    external_ip: Ipv4Addr,
+    /// (IP protocol, internal port) -> external address.
+    mappings: HashMap<(u8, u16), SocketAddr>,
+    map_requests: usize,
+    pcp_client_ips: Vec<IpAddr>,
+}
+
+/// Stand-in for a NAT-PMP / PCP gateway.
+struct Gateway {
+    addr: SocketAddr,
+    state: Arc<Mutex<GatewayState>>,
+}
+
+impl Gateway {
+    async fn start(ip: IpAddr, opts: GatewayOpts) -> Self {
+        let sock = tokio::net::UdpSocket::bind((ip, 0)).await.unwrap();
+        let addr = sock.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(GatewayState {
+            external_ip: EXTERNAL_IP,
+            mappings: HashMap::new(),
+            map_requests: 0,
+            pcp_client_ips: Vec::new(),
+        }));
+        let task_state = state.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1100];
+            loop {
+                let (len, from) = sock.recv_from(&mut buf).await.unwrap();
+                let resp = {
+                    let mut state = task_state.lock().unwrap();
+                    handle(&mut state, opts, &buf[..len], from)
+                };
+                if let Some(resp) = resp {
+                    sock.send_to(&resp, from).await.unwrap();
+                }
+            }
+        });
+        Self { addr, state }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, GatewayState> {
+        self.state.lock().unwrap()
+    }
+
+    async fn wait_for(&self, cond: impl Fn(&GatewayState) -> bool) {
+        timeout(TIMEOUT, async {
+            while !cond(&self.state()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+}
+
+fn be16(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes(buf[at..at + 2].try_into().unwrap())
+}
+
+fn be32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn grant(
+    state: &mut GatewayState,
+    protocol: u8,
+    internal: SocketAddr,
+    suggested_port: u16,
+    lifetime: u32,
+) -> SocketAddr {
+    state.map_requests += 1;
+    if lifetime == 0 {
+        state.mappings.remove(&(protocol, internal.port()));
+        return (Ipv4Addr::UNSPECIFIED, 0).into();
+    }
+    let external = if internal.is_ipv6() {
+        // Firewall pinhole, no translation.
+        internal
+    } else {
+        let port = match suggested_port {
+            0 => 40000 + internal.port() % 1000,
+            port => port,
+        };
+        (state.external_ip, port).into()
+    };
+    state.mappings.insert((protocol, internal.port()), external);
+    external
+}
+
+fn handle(
+    state: &mut GatewayState,
+    opts: GatewayOpts,
+    buf: &[u8],
+    from: SocketAddr,
+) -> Option<Vec<u8>> {
+    match buf[0] {
+        0 => handle_nat_pmp(state, opts, buf),
+        2 if opts.pcp => handle_pcp(state, opts, buf, from),
+        _ if opts.drop_pcp => None,
+        _ => {
+            // Unsupported version, answered as NAT-PMP.
+            let mut resp = vec![0, 128 | buf[1]];
+            resp.extend_from_slice(&1u16.to_be_bytes());
+            resp.extend_from_slice(&[0; 4]);
+            Some(resp)
+        }
+    }
+}
+
+fn handle_nat_pmp(state: &mut GatewayState, opts: GatewayOpts, buf: &[u8]) -> Option<Vec<u8>> {
+    let op = buf[1];
+    let mut resp = vec![0, 128 + op, 0, 0, 0, 0, 0, 0];
+    if op == 0 {
+        resp.extend_from_slice(&state.external_ip.octets());
+        return Some(resp);
+    }
+    let internal_port = be16(buf, 4);
+    let lifetime = be32(buf, 8).min(opts.max_lifetime);
+    let external = if opts.fail != 0 {
+        resp[3] = opts.fail;
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        let protocol = if op == 1 { 17 } else { 6 };
+        let internal = (Ipv4Addr::LOCALHOST, internal_port).into();
+        grant(state, protocol, internal, be16(buf, 6), lifetime)
+    };
+    resp.extend_from_slice(&internal_port.to_be_bytes());
+    resp.extend_from_slice(&external.port().to_be_bytes());
+    resp.extend_from_slice(&lifetime.to_be_bytes());
+    Some(resp)
+}
+
+fn handle_pcp(
+    state: &mut GatewayState,
+    opts: GatewayOpts,
+    buf: &[u8],
+    from: SocketAddr,
+) -> Option<Vec<u8>> {
+    let client_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&buf[8..24]).unwrap()).to_canonical();
+    state.pcp_client_ips.push(client_ip);
+    let protocol = buf[36];
+    let internal_port = be16(buf, 40);
+    let lifetime = be32(buf, 4).min(opts.max_lifetime);
+
+    let mut result = opts.fail;
+    if client_ip != from.ip().to_canonical() {
+        // ADDRESS_MISMATCH
+        result = 12;
+    }
+    let external = if result == 0 {
+        let internal = (client_ip, internal_port).into();
+        grant(state, protocol, internal, be16(buf, 42), lifetime)
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let external_ip = match external.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+
+    let mut resp = vec![2, 0x81, 0, result];
+    resp.extend_from_slice(&lifetime.to_be_bytes());
+    resp.extend_from_slice(&[0; 16]);
+    resp.extend_from_slice(&buf[24..40]);
+    resp.extend_from_slice(&internal_port.to_be_bytes());
+    resp.extend_from_slice(&external.port().to_be_bytes());
+    resp.extend_from_slice(&external_ip.octets());
+    Some(resp)
+}
+
+fn opts() -> PortMappingOpts {
+    PortMappingOpts {
+        rto: Duration::from_millis(50),
+        max_requests: 3,
+        ..Default::default()
+    }
+}
+
+fn bind_tcp(ip: IpAddr) -> TcpListener {
+    TcpListener::bind_tcp((ip, 0).into(), BindOpts::default()).unwrap()
+}
+
+#[tokio::test]
+async fn test_nat_pmp_fallback_and_delete() {
+    let gateway = Gateway::start(
+        Ipv4Addr::LOCALHOST.into(),
+        GatewayOpts {
+            pcp: false,
+            ..Default::default()
+        },
+    )
+    .await;
+    let listener = bind_tcp(Ipv4Addr::LOCALHOST.into());
+    let port = listener.bind_addr().port();
+
+    let mapping = PortMapping::map_tcp(&listener, gateway.addr, opts())
+        .await
+        .unwrap();
+    assert_eq!(mapping.protocol(), PortMappingProtocol::NatPmp);
+    let expected: SocketAddr = (EXTERNAL_IP, 40000 + port % 1000).into();
+    assert_eq!(mapping.external_addr(), expected);
+    assert_eq!(gateway.state().mappings.get(&(6, port)), Some(&expected));
+
+    mapping.delete().await.unwrap();
+    assert!(gateway.state().mappings.is_empty());
+}
+
+#[tokio::test]
+async fn test_nat_pmp_fallback_on_pcp_timeout() {
+    let gateway = Gateway::start(
+        Ipv4Addr::LOCALHOST.into(),
+        GatewayOpts {
+            pcp: false,
+            drop_pcp: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    let listener = bind_tcp(Ipv4Addr::LOCALHOST.into());
+
+    // The full schedule would take 50ms * (2^9 - 1).
+    let mapping = timeout(
+        TIMEOUT,
+        PortMapping::map_tcp(
+            &listener,
+            gateway.addr,
+            PortMappingOpts {
+                max_requests: 9,
+                ..opts()
+            },
+        ),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(mapping.protocol(), PortMappingProtocol::NatPmp);
+    assert_eq!(mapping.external_addr().ip(), EXTERNAL_IP);
+}
+
+#[tokio::test]
+async fn test_pcp_udp_delete_on_drop() {
+    let gateway = Gateway::start(Ipv4Addr::LOCALHOST.into(), GatewayOpts::default()).await;
+    let sock = UdpSocket::bind_udp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let port = sock.bind_addr().port();
+
+    let mapping = PortMapping::map_udp(
+        &sock,
+        gateway.addr,
+        PortMappingOpts {
+            external_port: Some(45678),
+            ..opts()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(mapping.protocol(), PortMappingProtocol::Pcp);
+    assert_eq!(mapping.external_addr(), (EXTERNAL_IP, 45678).into());
+    assert_eq!(
+        gateway.state().pcp_client_ips,
+        vec![IpAddr::from(Ipv4Addr::LOCALHOST)]
+    );
+    assert!(gateway.state().mappings.contains_key(&(17, port)));
+
+    drop(mapping);
+    gateway.wait_for(|s| s.mappings.is_empty()).await;
+}
+
+#[tokio::test]
+async fn test_renewal_reports_new_external_addr() {
+    let gateway = Gateway::start(
+        Ipv4Addr::LOCALHOST.into(),
+        GatewayOpts {
+            max_lifetime: 2,
+            ..Default::default()
+        },
+    )
+    .await;
+    let listener = bind_tcp(Ipv4Addr::LOCALHOST.into());
+    let mapping = PortMapping::map_tcp(&listener, gateway.addr, opts())
+        .await
+        .unwrap();
+    let port = mapping.external_addr().port();
+    let mut watch = mapping.watch_external_addr();
+
+    let new_ip = Ipv4Addr::new(198, 51, 100, 1);
+    gateway.state().external_ip = new_ip;
+    timeout(TIMEOUT, watch.changed()).await.unwrap().unwrap();
+    assert_eq!(mapping.external_addr(), (new_ip, port).into());
+    assert!(gateway.state().map_requests >= 2);
+}
+
+#[tokio::test]
+async fn test_pcp_ipv6_pinhole() {
+    let gateway = Gateway::start(Ipv6Addr::LOCALHOST.into(), GatewayOpts::default()).await;
+    let listener = bind_tcp(Ipv6Addr::UNSPECIFIED.into());
+    let port = listener.bind_addr().port();
+
+    let mapping = PortMapping::map_tcp(&listener, gateway.addr, opts())
+        .await
+        .unwrap();
+    assert_eq!(mapping.protocol(), PortMappingProtocol::Pcp);
+    assert_eq!(mapping.external_addr(), (Ipv6Addr::LOCALHOST, port).into());
+    mapping.delete().await.unwrap();
+    assert!(gateway.state().mappings.is_empty());
+}
+
+#[tokio::test]
+async fn test_nat_pmp_rejects_ipv6_gateway() {
+    let listener = bind_tcp(Ipv6Addr::UNSPECIFIED.into());
+    let res = PortMapping::map_tcp(
+        &listener,
+        (Ipv6Addr::LOCALHOST, crate::PORT_MAPPING_PORT).into(),
+        PortMappingOpts {
+            protocol: PortMappingProtocol::NatPmp,
+            ..opts()
+        },
+    )
+    .await;
+    assert!(matches!(res, Err(Error::NatPmpIpv6)), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_result_codes() {
+    let gateway = Gateway::start(
+        Ipv4Addr::LOCALHOST.into(),
+        GatewayOpts {
+            // NOT_AUTHORIZED
+            fail: 2,
+            ..Default::default()
+        },
+    )
+    .await;
+    let listener = bind_tcp(Ipv4Addr::LOCALHOST.into());
+
+    let res = PortMapping::map_tcp(&listener, gateway.addr, opts()).await;
+    assert!(matches!(res, Err(Error::PcpResult(2))), "{res:?}");
+
+    let nat_pmp = PortMappingOpts {
+        protocol: PortMappingProtocol::NatPmp,
+        ..opts()
+    };
+    let res = PortMapping::map_tcp(&listener, gateway.addr, nat_pmp).await;
+    assert!(matches!(res, Err(Error::NatPmpResult(2))), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_timeout() {
+    let silent = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let listener = bind_tcp(Ipv4Addr::LOCALHOST.into());
+    let res = PortMapping::map_tcp(
+        &listener,
+        silent.local_addr().unwrap(),
+        PortMappingOpts {
+            rto: Duration::from_millis(10),
+            max_requests: 2,
+            ..opts()
+        },
+    )
+    .await;
+    assert!(
+        matches!(res, Err(Error::PortMappingTimeout { gateway }) if gateway == silent.local_addr().unwrap()),
+        "{res:?}"
+    );
+}
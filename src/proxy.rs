@@ -0,0 +1,92 @@
+#[cfg(test)]
+mod tests;
+
+mod http;
+mod no_proxy;
+pub(crate) mod socks5;
+mod udp;
+
+use std::net::SocketAddr;
+
+use tokio::net::TcpStream;
+use tracing::debug;
+
+pub use no_proxy::NoProxy;
+pub use udp::Socks5UdpSocket;
+
+use crate::{ConnectOpts, Error, connect::tcp_connect_direct};
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyAuth")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Proxy {
+    /// SOCKS5 (RFC 1928), with optional username/password auth (RFC 1929).
+    Socks5 {
+        addr: SocketAddr,
+        auth: Option<ProxyAuth>,
+    },
+    /// HTTP/1.1 CONNECT tunnel, with optional Basic auth. TCP only.
+    Http {
+        addr: SocketAddr,
+        auth: Option<ProxyAuth>,
+    },
+}
+
+impl Proxy {
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            Proxy::Socks5 { addr, .. } | Proxy::Http { addr, .. } => *addr,
+        }
+    }
+}
+
+/// Where the proxy should connect to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ProxyTarget<'a> {
+    Addr(SocketAddr),
+    /// Resolved by the proxy.
+    Host(&'a str, u16),
+}
+
+/// Connect to `target` through `proxy`. All other `opts` apply to the connection to the proxy,
+/// and `timeout` covers the proxy handshake too.
+pub(crate) async fn connect_via(
+    proxy: &Proxy,
+    target: ProxyTarget<'_>,
+    opts: ConnectOpts<'_>,
+) -> crate::Result<TcpStream> {
+    let proxy_opts = ConnectOpts {
+        timeout: None,
+        ..opts
+    };
+    let connect = async {
+        let mut stream = tcp_connect_direct(proxy.addr(), proxy_opts).await?;
+        match proxy {
+            Proxy::Socks5 { auth, .. } => {
+                socks5::handshake(&mut stream, auth.as_ref(), socks5::Command::Connect, target)
+                    .await?;
+            }
+            Proxy::Http { auth, .. } => http::handshake(&mut stream, auth.as_ref(), target).await?,
+        }
+        debug!(proxy=?proxy.addr(), ?target, "connected through proxy");
+        Ok(stream)
+    };
+    match opts.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| Error::ConnectTimeout)?,
+        None => connect.await,
+    }
+}
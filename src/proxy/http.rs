@@ -0,0 +1,95 @@
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::trace;
+
+use super::{ProxyAuth, ProxyTarget};
+use crate::{Error, addr::TryToV4};
+
+/// Max size of the proxy's response headers.
+const MAX_RESPONSE_LEN: usize = 8192;
+
+pub(crate) fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// "host:port" as used in the request target and Host header, with IPv6 literals in brackets.
+pub(crate) fn authority(target: ProxyTarget<'_>) -> String {
+    match target {
+        ProxyTarget::Addr(addr) => addr.try_to_ipv4().to_string(),
+        ProxyTarget::Host(host, port) if host.contains(':') && !host.starts_with('[') => {
+            format!("[{host}]:{port}")
+        }
+        ProxyTarget::Host(host, port) => format!("{host}:{port}"),
+    }
+}
+
+fn request(target: ProxyTarget<'_>, auth: Option<&ProxyAuth>) -> String {
+    let authority = authority(target);
+    let mut req = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let Some(auth) = auth {
+        let credentials = base64(format!("{}:{}", auth.username, auth.password).as_bytes());
+        req.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    req.push_str("\r\n");
+    req
+}
+
+/// Establish a tunnel with HTTP/1.1 CONNECT.
+pub(crate) async fn handshake(
+    stream: &mut TcpStream,
+    auth: Option<&ProxyAuth>,
+    target: ProxyTarget<'_>,
+) -> crate::Result<()> {
+    trace!(?target, "sending HTTP CONNECT");
+    stream
+        .write_all(request(target, auth).as_bytes())
+        .await
+        .map_err(Error::HttpProxy)?;
+
+    // Read byte by byte so that nothing past the headers is consumed from the tunnel.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE_LEN {
+            return Err(Error::HttpProxyInvalidResponse);
+        }
+        response.push(stream.read_u8().await.map_err(Error::HttpProxy)?);
+    }
+    let status_line = response
+        .split(|b| *b == b'\n')
+        .next()
+        .and_then(|l| std::str::from_utf8(l).ok())
+        .ok_or(Error::HttpProxyInvalidResponse)?
+        .trim_end();
+    let mut parts = status_line.split_ascii_whitespace();
+    let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+        return Err(Error::HttpProxyInvalidResponse);
+    };
+    if !version.starts_with("HTTP/1.") || status.len() != 3 {
+        return Err(Error::HttpProxyInvalidResponse);
+    }
+    if !status.starts_with('2') {
+        return Err(Error::HttpProxyStatus {
+            status_line: status_line.to_owned(),
+        });
+    }
+    Ok(())
+}
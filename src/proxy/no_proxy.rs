@@ -0,0 +1,110 @@
+use std::{net::IpAddr, str::FromStr};
+
+use crate::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Rule {
+    /// "localhost": the hostname, its subdomains and all loopback addresses.
+    Localhost,
+    Cidr {
+        net: IpAddr,
+        prefix: u8,
+    },
+    /// A hostname and its subdomains.
+    Domain(String),
+}
+
+/// Destinations that are connected to directly even when a proxy is set.
+///
+/// Parsed from a comma-separated list like `NO_PROXY`, e.g. "localhost,10.0.0.0/8,::1,example.com".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NoProxy {
+    rules: Vec<Rule>,
+}
+
+fn mask(ip: IpAddr, prefix: u8) -> u128 {
+    let (bits, width) = match ip {
+        IpAddr::V4(ip) => (u32::from(ip) as u128, 32),
+        IpAddr::V6(ip) => (u128::from(ip), 128),
+    };
+    let prefix = prefix.min(width) as u32;
+    if prefix == 0 {
+        0
+    } else {
+        bits >> (width as u32 - prefix)
+    }
+}
+
+fn domain_matches(domain: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    host.eq_ignore_ascii_case(domain)
+        || (host.len() > domain.len()
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+            && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain))
+}
+
+impl NoProxy {
+    pub fn matches_ip(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.rules.iter().any(|rule| match rule {
+            Rule::Localhost => ip.is_loopback(),
+            Rule::Cidr { net, prefix } => {
+                net.is_ipv4() == ip.is_ipv4() && mask(*net, *prefix) == mask(ip, *prefix)
+            }
+            Rule::Domain(_) => false,
+        })
+    }
+
+    /// `host` may be a hostname or an IP address.
+    pub fn matches_host(&self, host: &str) -> bool {
+        let unbracketed = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+            return self.matches_ip(ip);
+        }
+        self.rules.iter().any(|rule| match rule {
+            Rule::Localhost => domain_matches("localhost", host),
+            Rule::Domain(domain) => domain_matches(domain, host),
+            Rule::Cidr { .. } => false,
+        })
+    }
+}
+
+impl FromStr for NoProxy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |rule: &str| Error::NoProxyInvalid(rule.to_owned());
+        let mut rules = Vec::new();
+        for rule in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let parsed = if rule.eq_ignore_ascii_case("localhost") {
+                Rule::Localhost
+            } else if let Some((net, prefix)) = rule.split_once('/') {
+                let net: IpAddr = net.parse().map_err(|_| invalid(rule))?;
+                let net = net.to_canonical();
+                let prefix: u8 = prefix.parse().map_err(|_| invalid(rule))?;
+                let max = if net.is_ipv4() { 32 } else { 128 };
+                if prefix > max {
+                    return Err(invalid(rule));
+                }
+                Rule::Cidr { net, prefix }
+            } else if let Ok(ip) = rule.parse::<IpAddr>() {
+                let ip = ip.to_canonical();
+                Rule::Cidr {
+                    net: ip,
+                    prefix: if ip.is_ipv4() { 32 } else { 128 },
+                }
+            } else {
+                let domain = rule.trim_start_matches("*.").trim_start_matches('.');
+                if domain.is_empty() || domain.contains(['/', ':', ' ']) {
+                    return Err(invalid(rule));
+                }
+                Rule::Domain(domain.to_owned())
+            };
+            rules.push(parsed);
+        }
+        Ok(Self { rules })
+    }
+}
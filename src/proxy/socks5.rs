@@ -0,0 +1,162 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::trace;
+
+use super::{ProxyAuth, ProxyTarget};
+use crate::{Error, addr::TryToV4};
+
+const VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_USERNAME_PASSWORD: u8 = 2;
+const METHOD_NO_ACCEPTABLE: u8 = 0xff;
+
+pub(crate) const ATYP_IPV4: u8 = 1;
+pub(crate) const ATYP_DOMAIN: u8 = 3;
+pub(crate) const ATYP_IPV6: u8 = 4;
+
+#[derive(Clone, Copy, Debug)]
+#[repr(u8)]
+pub(crate) enum Command {
+    Connect = 1,
+    UdpAssociate = 3,
+}
+
+/// Append SOCKS5 ATYP, address and port.
+pub(crate) fn encode_addr(buf: &mut Vec<u8>, target: ProxyTarget<'_>) -> crate::Result<()> {
+    match target {
+        ProxyTarget::Addr(addr) => match addr.try_to_ipv4() {
+            SocketAddr::V4(addr) => {
+                buf.push(ATYP_IPV4);
+                buf.extend_from_slice(&addr.ip().octets());
+                buf.extend_from_slice(&addr.port().to_be_bytes());
+            }
+            SocketAddr::V6(addr) => {
+                buf.push(ATYP_IPV6);
+                buf.extend_from_slice(&addr.ip().octets());
+                buf.extend_from_slice(&addr.port().to_be_bytes());
+            }
+        },
+        ProxyTarget::Host(host, port) => {
+            let len = u8::try_from(host.len()).map_err(|_| Error::Socks5HostnameTooLong)?;
+            buf.push(ATYP_DOMAIN);
+            buf.push(len);
+            buf.extend_from_slice(host.as_bytes());
+            buf.extend_from_slice(&port.to_be_bytes());
+        }
+    }
+    Ok(())
+}
+
+fn reply_error(code: u8) -> Error {
+    match code {
+        1 => Error::Socks5GeneralFailure,
+        2 => Error::Socks5NotAllowed,
+        3 => Error::Socks5NetworkUnreachable,
+        4 => Error::Socks5HostUnreachable,
+        5 => Error::Socks5ConnectionRefused,
+        6 => Error::Socks5TtlExpired,
+        7 => Error::Socks5CommandNotSupported,
+        8 => Error::Socks5AddressTypeNotSupported,
+        code => Error::Socks5UnknownReply(code),
+    }
+}
+
+async fn authenticate(stream: &mut TcpStream, auth: Option<&ProxyAuth>) -> crate::Result<()> {
+    let greeting: &[u8] = match auth {
+        Some(_) => &[VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD],
+        None => &[VERSION, 1, METHOD_NO_AUTH],
+    };
+    stream.write_all(greeting).await.map_err(Error::Socks5)?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await.map_err(Error::Socks5)?;
+    if reply[0] != VERSION {
+        return Err(Error::Socks5InvalidReply);
+    }
+    match (reply[1], auth) {
+        (METHOD_NO_AUTH, _) => Ok(()),
+        (METHOD_USERNAME_PASSWORD, Some(auth)) => {
+            let (Ok(ulen), Ok(plen)) = (
+                u8::try_from(auth.username.len()),
+                u8::try_from(auth.password.len()),
+            ) else {
+                return Err(Error::Socks5CredentialsTooLong);
+            };
+            let mut req = Vec::with_capacity(3 + ulen as usize + plen as usize);
+            req.push(AUTH_VERSION);
+            req.push(ulen);
+            req.extend_from_slice(auth.username.as_bytes());
+            req.push(plen);
+            req.extend_from_slice(auth.password.as_bytes());
+            stream.write_all(&req).await.map_err(Error::Socks5)?;
+            stream.read_exact(&mut reply).await.map_err(Error::Socks5)?;
+            if reply[0] != AUTH_VERSION {
+                return Err(Error::Socks5InvalidReply);
+            }
+            if reply[1] != 0 {
+                return Err(Error::Socks5AuthFailed);
+            }
+            Ok(())
+        }
+        (METHOD_NO_ACCEPTABLE, _) => Err(Error::Socks5NoAcceptableAuth),
+        _ => Err(Error::Socks5InvalidReply),
+    }
+}
+
+/// Run the SOCKS5 handshake for `cmd` and return the address the proxy bound for it, unless it
+/// replied with a hostname.
+pub(crate) async fn handshake(
+    stream: &mut TcpStream,
+    auth: Option<&ProxyAuth>,
+    cmd: Command,
+    target: ProxyTarget<'_>,
+) -> crate::Result<Option<SocketAddr>> {
+    authenticate(stream, auth).await?;
+
+    let mut req = vec![VERSION, cmd as u8, 0];
+    encode_addr(&mut req, target)?;
+    trace!(?cmd, ?target, "sending SOCKS5 request");
+    stream.write_all(&req).await.map_err(Error::Socks5)?;
+
+    let mut header = [0u8; 4];
+    stream
+        .read_exact(&mut header)
+        .await
+        .map_err(Error::Socks5)?;
+    if header[0] != VERSION {
+        return Err(Error::Socks5InvalidReply);
+    }
+    if header[1] != 0 {
+        return Err(reply_error(header[1]));
+    }
+    let bound = match header[3] {
+        ATYP_IPV4 => {
+            let mut buf = [0u8; 6];
+            stream.read_exact(&mut buf).await.map_err(Error::Socks5)?;
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&buf[..4]).unwrap());
+            Some(SocketAddr::from((ip, u16::from_be_bytes([buf[4], buf[5]]))))
+        }
+        ATYP_IPV6 => {
+            let mut buf = [0u8; 18];
+            stream.read_exact(&mut buf).await.map_err(Error::Socks5)?;
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&buf[..16]).unwrap());
+            Some(SocketAddr::from((
+                ip,
+                u16::from_be_bytes([buf[16], buf[17]]),
+            )))
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await.map_err(Error::Socks5)?;
+            let mut buf = vec![0u8; len as usize + 2];
+            stream.read_exact(&mut buf).await.map_err(Error::Socks5)?;
+            None
+        }
+        _ => return Err(Error::Socks5InvalidReply),
+    };
+    Ok(bound)
+}
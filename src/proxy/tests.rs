@@ -0,0 +1,746 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::{
+    BindOpts, ConnectOpts, Error, NoProxy, PollSendToVectored, Proxy, ProxyAuth, Socks5UdpSocket,
+    TcpListener, UdpSocket, tcp_connect, tcp_connect_host,
+};
+
+use super::{
+    ProxyTarget,
+    http::{authority, base64},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Requested {
+    Addr(SocketAddr),
+    Host(String, u16),
+}
+
+/// A minimal in-process SOCKS5 server.
+pub(crate) struct Socks5Server {
+    pub addr: SocketAddr,
+    pub requests: Arc<Mutex<Vec<Requested>>>,
+    close_controls: Arc<tokio::sync::Notify>,
+}
+
+impl Socks5Server {
+    pub async fn start(auth: Option<ProxyAuth>, reply: u8) -> Self {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests: Arc<Mutex<Vec<Requested>>> = Default::default();
+        let close_controls: Arc<tokio::sync::Notify> = Default::default();
+        let (r, c) = (requests.clone(), close_controls.clone());
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = listener.accept().await.unwrap();
+                let (auth, r, c) = (auth.clone(), r.clone(), c.clone());
+                tokio::spawn(async move {
+                    let _ = serve(conn, auth, reply, r, c).await;
+                });
+            }
+        });
+        Self {
+            addr,
+            requests,
+            close_controls,
+        }
+    }
+
+    /// Close the control connections of all UDP associations.
+    pub fn close_controls(&self) {
+        self.close_controls.notify_waiters();
+    }
+
+    pub fn proxy(&self, auth: Option<ProxyAuth>) -> Proxy {
+        Proxy::Socks5 {
+            addr: self.addr,
+            auth,
+        }
+    }
+}
+
+pub(crate) async fn read_request_addr(conn: &mut TcpStream) -> std::io::Result<Requested> {
+    let atyp = conn.read_u8().await?;
+    Ok(match atyp {
+        1 => {
+            let mut ip = [0u8; 4];
+            conn.read_exact(&mut ip).await?;
+            Requested::Addr((Ipv4Addr::from(ip), conn.read_u16().await?).into())
+        }
+        4 => {
+            let mut ip = [0u8; 16];
+            conn.read_exact(&mut ip).await?;
+            Requested::Addr((Ipv6Addr::from(ip), conn.read_u16().await?).into())
+        }
+        3 => {
+            let mut host = vec![0u8; conn.read_u8().await? as usize];
+            conn.read_exact(&mut host).await?;
+            Requested::Host(String::from_utf8(host).unwrap(), conn.read_u16().await?)
+        }
+        _ => panic!("bad atyp {atyp}"),
+    })
+}
+
+/// Greeting and auth. Returns false if the client was rejected.
+pub(crate) async fn serve_auth(
+    conn: &mut TcpStream,
+    auth: &Option<ProxyAuth>,
+) -> std::io::Result<bool> {
+    assert_eq!(conn.read_u8().await?, 5);
+    let mut methods = vec![0u8; conn.read_u8().await? as usize];
+    conn.read_exact(&mut methods).await?;
+    let Some(auth) = auth else {
+        conn.write_all(&[5, 0]).await?;
+        return Ok(true);
+    };
+    if !methods.contains(&2) {
+        conn.write_all(&[5, 0xff]).await?;
+        return Ok(false);
+    }
+    conn.write_all(&[5, 2]).await?;
+    assert_eq!(conn.read_u8().await?, 1);
+    let mut username = vec![0u8; conn.read_u8().await? as usize];
+    conn.read_exact(&mut username).await?;
+    let mut password = vec![0u8; conn.read_u8().await? as usize];
+    conn.read_exact(&mut password).await?;
+    let ok = username == auth.username.as_bytes() && password == auth.password.as_bytes();
+    conn.write_all(&[1, if ok { 0 } else { 1 }]).await?;
+    Ok(ok)
+}
+
+async fn serve(
+    mut conn: TcpStream,
+    auth: Option<ProxyAuth>,
+    reply: u8,
+    requests: Arc<Mutex<Vec<Requested>>>,
+    close_controls: Arc<tokio::sync::Notify>,
+) -> std::io::Result<()> {
+    if !serve_auth(&mut conn, &auth).await? {
+        return Ok(());
+    }
+    let mut header = [0u8; 3];
+    conn.read_exact(&mut header).await?;
+    let requested = read_request_addr(&mut conn).await?;
+    requests.lock().unwrap().push(requested.clone());
+    if reply != 0 {
+        conn.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        return Ok(());
+    }
+    match header {
+        [5, 1, 0] => {}
+        [5, 3, 0] => {
+            let Requested::Addr(client) = requested else {
+                panic!("expected client address")
+            };
+            return serve_udp(conn, client, close_controls).await;
+        }
+        _ => panic!("unexpected request {header:?}"),
+    }
+
+    let mut upstream = match requested {
+        Requested::Addr(addr) => TcpStream::connect(addr).await?,
+        Requested::Host(host, port) => TcpStream::connect((host.as_str(), port)).await?,
+    };
+    let local = upstream.local_addr()?;
+    let mut reply = vec![5, 0, 0];
+    crate::proxy::socks5::encode_addr(&mut reply, crate::proxy::ProxyTarget::Addr(local)).unwrap();
+    conn.write_all(&reply).await?;
+    tokio::io::copy_bidirectional(&mut conn, &mut upstream).await?;
+    Ok(())
+}
+
+async fn serve_udp(
+    mut conn: TcpStream,
+    client: SocketAddr,
+    close_controls: Arc<tokio::sync::Notify>,
+) -> std::io::Result<()> {
+    let relay = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    // Reply with an unspecified address, meaning "same IP as the proxy".
+    conn.write_all(&[5, 0, 0, 1, 0, 0, 0, 0]).await?;
+    conn.write_u16(relay.local_addr()?.port()).await?;
+
+    let relay_loop = async {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (len, from) = relay.recv_from(&mut buf).await?;
+            if from == client {
+                let (header_len, target) = super::udp::decode_header(&buf[..len]).unwrap();
+                relay.send_to(&buf[header_len..len], target).await?;
+            } else {
+                let mut wrapped = vec![0, 0, 0];
+                super::socks5::encode_addr(&mut wrapped, super::ProxyTarget::Addr(from)).unwrap();
+                wrapped.extend_from_slice(&buf[..len]);
+                relay.send_to(&wrapped, client).await?;
+            }
+        }
+    };
+    let control_closed = async {
+        let mut buf = [0u8; 1];
+        tokio::select! {
+            _ = conn.read(&mut buf) => {},
+            _ = close_controls.notified() => {},
+        }
+    };
+    tokio::select! {
+        res = relay_loop => res,
+        _ = control_closed => Ok(()),
+    }
+}
+
+async fn assert_echo(mut stream: TcpStream, listener: &TcpListener) {
+    let (mut accepted, _) = timeout(TIMEOUT, listener.accept()).await.unwrap().unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    accepted.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn test_socks5_connect() {
+    let server = Socks5Server::start(None, 0).await;
+    let proxy = server.proxy(None);
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let opts = ConnectOpts {
+        proxy: Some(&proxy),
+        timeout: Some(TIMEOUT),
+        ..Default::default()
+    };
+
+    let stream = tcp_connect(listener.bind_addr(), opts).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), server.addr);
+    assert_echo(stream, &listener).await;
+
+    // IPv4-mapped addresses are sent as IPv4.
+    let mapped = SocketAddr::from((
+        Ipv4Addr::LOCALHOST.to_ipv6_mapped(),
+        listener.bind_addr().port(),
+    ));
+    let stream = tcp_connect(mapped, opts).await.unwrap();
+    assert_echo(stream, &listener).await;
+
+    assert_eq!(
+        *server.requests.lock().unwrap(),
+        vec![
+            Requested::Addr(listener.bind_addr()),
+            Requested::Addr(listener.bind_addr())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_socks5_username_password() {
+    let auth = ProxyAuth {
+        username: "user".into(),
+        password: "secret".into(),
+    };
+    let server = Socks5Server::start(Some(auth.clone()), 0).await;
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+
+    let proxy = server.proxy(Some(auth.clone()));
+    let opts = ConnectOpts {
+        proxy: Some(&proxy),
+        timeout: Some(TIMEOUT),
+        ..Default::default()
+    };
+    let stream = tcp_connect(listener.bind_addr(), opts).await.unwrap();
+    assert_echo(stream, &listener).await;
+
+    let proxy = server.proxy(Some(ProxyAuth {
+        password: "wrong".into(),
+        ..auth
+    }));
+    let res = tcp_connect(
+        listener.bind_addr(),
+        ConnectOpts {
+            proxy: Some(&proxy),
+            ..opts
+        },
+    )
+    .await;
+    assert!(matches!(res, Err(Error::Socks5AuthFailed)), "{res:?}");
+
+    let proxy = server.proxy(None);
+    let res = tcp_connect(
+        listener.bind_addr(),
+        ConnectOpts {
+            proxy: Some(&proxy),
+            ..opts
+        },
+    )
+    .await;
+    assert!(matches!(res, Err(Error::Socks5NoAcceptableAuth)), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_socks5_auth_reply_version() {
+    let server =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let proxy = Proxy::Socks5 {
+        addr: server.bind_addr(),
+        auth: Some(ProxyAuth {
+            username: "user".into(),
+            password: "secret".into(),
+        }),
+    };
+    let serve = async {
+        let (mut conn, _) = server.accept().await.unwrap();
+        let mut greeting = [0u8; 4];
+        conn.read_exact(&mut greeting).await.unwrap();
+        conn.write_all(&[5, 2]).await.unwrap();
+        let mut req = [0u8; 3 + 4 + 6];
+        conn.read_exact(&mut req).await.unwrap();
+        // Success, but with the SOCKS version instead of the auth subnegotiation version.
+        conn.write_all(&[5, 0]).await.unwrap();
+        conn
+    };
+    let connect = tcp_connect(
+        (Ipv4Addr::LOCALHOST, 1).into(),
+        ConnectOpts {
+            proxy: Some(&proxy),
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+    );
+    let (res, _conn) = tokio::join!(connect, serve);
+    assert!(matches!(res, Err(Error::Socks5InvalidReply)), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_socks5_remote_resolution() {
+    let server = Socks5Server::start(None, 0).await;
+    let proxy = server.proxy(None);
+    let listener =
+        TcpListener::bind_tcp((Ipv6Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap();
+    let port = listener.bind_addr().port();
+
+    let stream = tcp_connect_host(
+        "localhost",
+        port,
+        ConnectOpts {
+            proxy: Some(&proxy),
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_echo(stream, &listener).await;
+    assert_eq!(
+        *server.requests.lock().unwrap(),
+        vec![Requested::Host("localhost".into(), port)]
+    );
+}
+
+#[tokio::test]
+async fn test_socks5_reply_codes() {
+    let target = SocketAddr::from((Ipv4Addr::LOCALHOST, 1));
+    for code in 1..=9u8 {
+        let server = Socks5Server::start(None, code).await;
+        let proxy = server.proxy(None);
+        let res = tcp_connect(
+            target,
+            ConnectOpts {
+                proxy: Some(&proxy),
+                timeout: Some(TIMEOUT),
+                ..Default::default()
+            },
+        )
+        .await;
+        let ok = matches!(
+            (code, &res),
+            (1, Err(Error::Socks5GeneralFailure))
+                | (2, Err(Error::Socks5NotAllowed))
+                | (3, Err(Error::Socks5NetworkUnreachable))
+                | (4, Err(Error::Socks5HostUnreachable))
+                | (5, Err(Error::Socks5ConnectionRefused))
+                | (6, Err(Error::Socks5TtlExpired))
+                | (7, Err(Error::Socks5CommandNotSupported))
+                | (8, Err(Error::Socks5AddressTypeNotSupported))
+                | (9, Err(Error::Socks5UnknownReply(9)))
+        );
+        assert!(ok, "code {code}: {res:?}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_socks5_bind_device_applies_to_proxy_connection() {
+    let server = Socks5Server::start(None, 0).await;
+    let proxy = server.proxy(None);
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let name = crate::bind_device::tests::find_localhost_name();
+    let bd = crate::BindDevice::new_from_name(&name).unwrap();
+
+    let stream = tcp_connect(
+        listener.bind_addr(),
+        ConnectOpts {
+            proxy: Some(&proxy),
+            bind_device: Some(&bd),
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let device = socket2::SockRef::from(&stream).device().unwrap();
+    assert_eq!(device.as_deref(), Some(name.as_bytes()));
+    assert_echo(stream, &listener).await;
+}
+
+async fn associate(server: &Socks5Server) -> Socks5UdpSocket {
+    Socks5UdpSocket::associate(
+        &server.proxy(None),
+        ConnectOpts {
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_socks5_udp_associate() {
+    let server = Socks5Server::start(None, 0).await;
+    let sock = associate(&server).await;
+    assert_eq!(sock.relay_addr().ip(), server.addr.ip());
+
+    let peer = UdpSocket::bind_udp((Ipv6Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap();
+    let peer_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, peer.bind_addr().port()));
+    let mut buf = [0u8; 100];
+
+    sock.send_to(b"ping", peer_addr).await.unwrap();
+    let (len, from) = timeout(TIMEOUT, peer.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..len], b"ping");
+    assert_eq!(from, sock.relay_addr());
+
+    // IPv4-mapped targets are sent as IPv4, the relay couldn't reach them otherwise.
+    let mapped = SocketAddr::from((Ipv4Addr::LOCALHOST.to_ipv6_mapped(), peer_addr.port()));
+    let sent = std::future::poll_fn(|cx| {
+        sock.poll_send_to_vectored(
+            cx,
+            &[
+                std::io::IoSlice::new(b"hello "),
+                std::io::IoSlice::new(b"world"),
+            ],
+            mapped,
+        )
+    })
+    .await
+    .unwrap();
+    assert_eq!(sent, 11);
+    let (len, _) = timeout(TIMEOUT, peer.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..len], b"hello world");
+
+    peer.send_to(b"pong", sock.relay_addr()).await.unwrap();
+    let (len, from) = timeout(TIMEOUT, sock.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..len], b"pong");
+    assert_eq!(from, peer_addr);
+}
+
+#[tokio::test]
+async fn test_socks5_udp_fails_closed() {
+    let server = Socks5Server::start(None, 0).await;
+    let sock = associate(&server).await;
+    let mut buf = [0u8; 100];
+
+    let recv = sock.recv_from(&mut buf);
+    let close = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        server.close_controls();
+    };
+    let (res, _) = timeout(TIMEOUT, async { tokio::join!(recv, close) })
+        .await
+        .unwrap();
+    assert_eq!(
+        res.unwrap_err().kind(),
+        std::io::ErrorKind::ConnectionAborted
+    );
+
+    let res = sock.send_to(b"ping", (Ipv4Addr::LOCALHOST, 1).into()).await;
+    assert_eq!(
+        res.unwrap_err().kind(),
+        std::io::ErrorKind::ConnectionAborted
+    );
+}
+
+/// A minimal in-process HTTP CONNECT proxy. Replies with `status` instead of connecting, unless
+/// it's 200.
+struct HttpProxyServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl HttpProxyServer {
+    async fn start(status: &'static str, basic_auth: Option<&'static str>) -> Self {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests: Arc<Mutex<Vec<String>>> = Default::default();
+        let r = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = listener.accept().await.unwrap();
+                let r = r.clone();
+                tokio::spawn(async move {
+                    let _ = serve_http(conn, status, basic_auth, r).await;
+                });
+            }
+        });
+        Self { addr, requests }
+    }
+
+    fn proxy(&self, auth: Option<ProxyAuth>) -> Proxy {
+        Proxy::Http {
+            addr: self.addr,
+            auth,
+        }
+    }
+}
+
+async fn serve_http(
+    mut conn: TcpStream,
+    status: &str,
+    basic_auth: Option<&str>,
+    requests: Arc<Mutex<Vec<String>>>,
+) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        request.push(conn.read_u8().await?);
+    }
+    let request = String::from_utf8(request).unwrap();
+    requests.lock().unwrap().push(request.clone());
+
+    if let Some(basic_auth) = basic_auth
+        && !request.contains(&format!("Proxy-Authorization: Basic {basic_auth}\r\n"))
+    {
+        conn.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+            .await?;
+        return Ok(());
+    }
+    if status != "200" {
+        conn.write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+            .await?;
+        return Ok(());
+    }
+    let authority = request.split(' ').nth(1).unwrap();
+    let mut upstream = match authority.parse::<SocketAddr>() {
+        Ok(addr) => TcpStream::connect(addr).await?,
+        Err(_) => TcpStream::connect(authority).await?,
+    };
+    conn.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+        .await?;
+    tokio::io::copy_bidirectional(&mut conn, &mut upstream).await?;
+    Ok(())
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"user:secret"), "dXNlcjpzZWNyZXQ=");
+}
+
+#[test]
+fn test_http_authority() {
+    let v6 = (Ipv6Addr::LOCALHOST, 80).into();
+    assert_eq!(authority(ProxyTarget::Addr(v6)), "[::1]:80");
+    let mapped = (Ipv4Addr::LOCALHOST.to_ipv6_mapped(), 80).into();
+    assert_eq!(authority(ProxyTarget::Addr(mapped)), "127.0.0.1:80");
+    assert_eq!(authority(ProxyTarget::Host("::1", 80)), "[::1]:80");
+    assert_eq!(authority(ProxyTarget::Host("[::1]", 80)), "[::1]:80");
+    assert_eq!(
+        authority(ProxyTarget::Host("example.com", 443)),
+        "example.com:443"
+    );
+}
+
+#[tokio::test]
+async fn test_http_connect() {
+    let server = HttpProxyServer::start("200", None).await;
+    let proxy = server.proxy(None);
+    let listener =
+        TcpListener::bind_tcp((Ipv6Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let opts = ConnectOpts {
+        proxy: Some(&proxy),
+        timeout: Some(TIMEOUT),
+        ..Default::default()
+    };
+
+    let stream = tcp_connect(listener.bind_addr(), opts).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), server.addr);
+    assert_echo(stream, &listener).await;
+
+    let port = listener.bind_addr().port();
+    assert_eq!(
+        *server.requests.lock().unwrap(),
+        vec![format!(
+            "CONNECT [::1]:{port} HTTP/1.1\r\nHost: [::1]:{port}\r\n\r\n"
+        )]
+    );
+}
+
+#[tokio::test]
+async fn test_http_connect_basic_auth() {
+    let server = HttpProxyServer::start("200", Some("dXNlcjpzZWNyZXQ=")).await;
+    let listener =
+        TcpListener::bind_tcp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let auth = ProxyAuth {
+        username: "user".into(),
+        password: "secret".into(),
+    };
+
+    let proxy = server.proxy(Some(auth.clone()));
+    let opts = ConnectOpts {
+        proxy: Some(&proxy),
+        timeout: Some(TIMEOUT),
+        ..Default::default()
+    };
+    let stream = tcp_connect(listener.bind_addr(), opts).await.unwrap();
+    assert_echo(stream, &listener).await;
+
+    let proxy = server.proxy(Some(ProxyAuth {
+        password: "wrong".into(),
+        ..auth
+    }));
+    let res = tcp_connect(
+        listener.bind_addr(),
+        ConnectOpts {
+            proxy: Some(&proxy),
+            ..opts
+        },
+    )
+    .await;
+    match res {
+        Err(Error::HttpProxyStatus { status_line }) => {
+            assert_eq!(status_line, "HTTP/1.1 407 Proxy Authentication Required")
+        }
+        res => panic!("{res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_http_connect_error_status() {
+    let server = HttpProxyServer::start("403 Forbidden", None).await;
+    let proxy = server.proxy(None);
+    let res = tcp_connect_host(
+        "example.com",
+        443,
+        ConnectOpts {
+            proxy: Some(&proxy),
+            timeout: Some(TIMEOUT),
+            ..Default::default()
+        },
+    )
+    .await;
+    match res {
+        Err(Error::HttpProxyStatus { status_line }) => {
+            assert_eq!(status_line, "HTTP/1.1 403 Forbidden")
+        }
+        res => panic!("{res:?}"),
+    }
+    assert!(server.requests.lock().unwrap()[0].starts_with("CONNECT example.com:443 HTTP/1.1\r\n"));
+}
+
+#[test]
+fn test_no_proxy_rules() {
+    let no_proxy: NoProxy = "localhost, 10.0.0.0/8,fd00::/8, 192.168.1.1,.example.com"
+        .parse()
+        .unwrap();
+    assert!(no_proxy.matches_ip("127.0.0.2".parse().unwrap()));
+    assert!(no_proxy.matches_ip("::1".parse().unwrap()));
+    assert!(no_proxy.matches_ip("::ffff:10.1.2.3".parse().unwrap()));
+    assert!(no_proxy.matches_ip("fd12::1".parse().unwrap()));
+    assert!(no_proxy.matches_ip("192.168.1.1".parse().unwrap()));
+    assert!(!no_proxy.matches_ip("192.168.1.2".parse().unwrap()));
+    assert!(!no_proxy.matches_ip("11.0.0.1".parse().unwrap()));
+    assert!(!no_proxy.matches_ip("fe80::1".parse().unwrap()));
+
+    assert!(no_proxy.matches_host("localhost"));
+    assert!(no_proxy.matches_host("LOCALHOST."));
+    assert!(no_proxy.matches_host("[::1]"));
+    assert!(no_proxy.matches_host("example.com"));
+    assert!(no_proxy.matches_host("www.example.com"));
+    assert!(!no_proxy.matches_host("notexample.com"));
+    assert!(!no_proxy.matches_host("example.org"));
+
+    assert!(matches!(
+        "10.0.0.0/33".parse::<NoProxy>(),
+        Err(Error::NoProxyInvalid(..))
+    ));
+    assert!(matches!(
+        "10.0.0.x/8".parse::<NoProxy>(),
+        Err(Error::NoProxyInvalid(..))
+    ));
+}
+
+#[tokio::test]
+async fn test_no_proxy_bypasses_proxy() {
+    let server = HttpProxyServer::start("200", None).await;
+    let proxy = server.proxy(None);
+    let no_proxy: NoProxy = "localhost".parse().unwrap();
+    let listener =
+        TcpListener::bind_tcp((Ipv6Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap();
+    let opts = ConnectOpts {
+        proxy: Some(&proxy),
+        no_proxy: Some(&no_proxy),
+        timeout: Some(TIMEOUT),
+        ..Default::default()
+    };
+
+    let stream = tcp_connect(
+        (Ipv4Addr::LOCALHOST, listener.bind_addr().port()).into(),
+        opts,
+    )
+    .await
+    .unwrap();
+    assert_ne!(stream.peer_addr().unwrap(), server.addr);
+    assert_echo(stream, &listener).await;
+
+    let stream = tcp_connect_host("localhost", listener.bind_addr().port(), opts)
+        .await
+        .unwrap();
+    assert_ne!(stream.peer_addr().unwrap(), server.addr);
+    assert_echo(stream, &listener).await;
+
+    assert!(server.requests.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_http_proxy_udp_not_supported() {
+    let proxy = Proxy::Http {
+        addr: (Ipv4Addr::LOCALHOST, 1).into(),
+        auth: None,
+    };
+    let res = Socks5UdpSocket::associate(&proxy, ConnectOpts::default()).await;
+    assert!(matches!(res, Err(Error::ProxyUdpNotSupported)));
+}
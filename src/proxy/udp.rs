@@ -0,0 +1,215 @@
+use std::{
+    io::IoSlice,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+
+use futures::future::{Either, select};
+use tokio::net::TcpStream;
+use tracing::{debug, trace};
+
+use super::{
+    Proxy, ProxyTarget,
+    socks5::{self, ATYP_IPV4, ATYP_IPV6},
+};
+use crate::{
+    BindOpts, ConnectOpts, Error, PollSendToVectored, UdpSocket, addr::TryToV4,
+    connect::tcp_connect_direct,
+};
+
+/// A UDP socket relaying datagrams through a SOCKS5 proxy (UDP ASSOCIATE).
+///
+/// The association lives as long as the control TCP connection. Once the proxy closes it, all
+/// sends and receives fail with [`std::io::ErrorKind::ConnectionAborted`], so that traffic never
+/// falls back to going out directly.
+pub struct Socks5UdpSocket {
+    socket: UdpSocket,
+    relay: SocketAddr,
+    control: TcpStream,
+    closed: AtomicBool,
+}
+
+impl Socks5UdpSocket {
+    /// Set up a UDP association with `proxy`. `opts` apply to the control connection, and
+    /// `bind_device` and `fwmark` to the local UDP socket too.
+    pub async fn associate(proxy: &Proxy, opts: ConnectOpts<'_>) -> crate::Result<Self> {
+        let Proxy::Socks5 { addr, auth } = proxy else {
+            return Err(Error::ProxyUdpNotSupported);
+        };
+        let associate = async {
+            let mut control = tcp_connect_direct(
+                *addr,
+                ConnectOpts {
+                    timeout: None,
+                    ..opts
+                },
+            )
+            .await?;
+            let local_ip = control.local_addr().map_err(Error::LocalAddr)?.ip();
+            let socket = UdpSocket::bind_udp(
+                SocketAddr::new(local_ip, 0),
+                BindOpts {
+                    request_dualstack: false,
+                    device: opts.bind_device,
+                    fwmark: opts.fwmark,
+                    ..Default::default()
+                },
+            )?;
+            let relay = socks5::handshake(
+                &mut control,
+                auth.as_ref(),
+                socks5::Command::UdpAssociate,
+                ProxyTarget::Addr(socket.bind_addr()),
+            )
+            .await?
+            .ok_or(Error::Socks5InvalidReply)?;
+            // Proxies commonly reply with an unspecified address meaning "same as the proxy".
+            let relay = if relay.ip().is_unspecified() {
+                SocketAddr::new(addr.ip(), relay.port())
+            } else {
+                relay
+            }
+            .try_to_ipv4();
+            Ok::<_, Error>((socket, relay, control))
+        };
+        let (socket, relay, control) = match opts.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, associate)
+                .await
+                .map_err(|_| Error::ConnectTimeout)??,
+            None => associate.await?,
+        };
+        debug!(proxy=?addr, ?relay, local=?socket.bind_addr(), "SOCKS5 UDP association established");
+        Ok(Self {
+            socket,
+            relay,
+            control,
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// The proxy's address datagrams are relayed through.
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay
+    }
+
+    pub fn bind_addr(&self) -> SocketAddr {
+        self.socket.bind_addr()
+    }
+
+    fn check_control(&self) -> std::io::Result<()> {
+        let aborted = || {
+            std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "SOCKS5 control connection closed",
+            )
+        };
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(aborted());
+        }
+        let mut buf = [0u8; 64];
+        match self.control.try_read(&mut buf) {
+            // The proxy isn't supposed to send anything after the reply.
+            Ok(n) if n > 0 => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+            res => {
+                debug!(relay=?self.relay, ?res, "SOCKS5 control connection closed");
+                self.closed.store(true, Ordering::Relaxed);
+                Err(aborted())
+            }
+        }
+    }
+
+    fn header(target: SocketAddr) -> Vec<u8> {
+        let mut header = vec![0, 0, 0];
+        socks5::encode_addr(&mut header, ProxyTarget::Addr(target))
+            .expect("IP addresses always fit");
+        header
+    }
+
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        std::future::poll_fn(|cx| self.poll_send_to_vectored(cx, &[IoSlice::new(buf)], target))
+            .await
+    }
+
+    pub fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<std::io::Result<usize>> {
+        self.poll_send_to_vectored(cx, &[IoSlice::new(buf)], target)
+    }
+
+    /// Receive a datagram relayed by the proxy. Datagrams from anywhere else, fragmented ones and
+    /// ones from hostnames are skipped.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        loop {
+            self.check_control()?;
+            let received = {
+                let recv = std::pin::pin!(self.socket.recv_from(buf));
+                let closed = std::pin::pin!(self.control.readable());
+                match select(recv, closed).await {
+                    Either::Left((res, _)) => Some(res?),
+                    Either::Right(..) => None,
+                }
+            };
+            let Some((len, from)) = received else {
+                continue;
+            };
+            if from != self.relay {
+                trace!(?from, relay=?self.relay, "ignoring datagram not from the SOCKS5 relay");
+                continue;
+            }
+            match decode_header(&buf[..len]) {
+                Some((header_len, addr)) => {
+                    buf.copy_within(header_len..len, 0);
+                    return Ok((len - header_len, addr.try_to_ipv4()));
+                }
+                None => trace!(len, "ignoring invalid SOCKS5 UDP datagram"),
+            }
+        }
+    }
+}
+
+/// Parse the SOCKS5 UDP request header, returning its length and the source address.
+pub(crate) fn decode_header(buf: &[u8]) -> Option<(usize, SocketAddr)> {
+    let (&[_, _, frag, atyp], rest) = buf.split_first_chunk::<4>()?;
+    if frag != 0 {
+        return None;
+    }
+    let (ip, rest) = match atyp {
+        ATYP_IPV4 => {
+            let (ip, rest) = rest.split_first_chunk::<4>()?;
+            (Ipv4Addr::from(*ip).into(), rest)
+        }
+        ATYP_IPV6 => {
+            let (ip, rest) = rest.split_first_chunk::<16>()?;
+            (Ipv6Addr::from(*ip).into(), rest)
+        }
+        // Hostnames can't be reported as a SocketAddr.
+        _ => return None,
+    };
+    let (port, rest) = rest.split_first_chunk::<2>()?;
+    Some((
+        buf.len() - rest.len(),
+        SocketAddr::new(ip, u16::from_be_bytes(*port)),
+    ))
+}
+
+impl PollSendToVectored for Socks5UdpSocket {
+    fn poll_send_to_vectored(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+        target: SocketAddr,
+    ) -> Poll<std::io::Result<usize>> {
+        self.check_control()?;
+        let header = Self::header(target);
+        let mut slices = Vec::with_capacity(bufs.len() + 1);
+        slices.push(IoSlice::new(&header));
+        slices.extend_from_slice(bufs);
+        let sent = std::task::ready!(self.socket.poll_send_to_vectored(cx, &slices, self.relay))?;
+        Poll::Ready(Ok(sent.saturating_sub(header.len())))
+    }
+}
@@ -0,0 +1,147 @@
+#[cfg(test)]
+mod tests;
+
+use std::net::SocketAddr;
+
+use socket2::SockFilter;
+use tracing::debug;
+
+use crate::{BindOpts, Error, ReuseportGuard, UdpSocket};
+
+/// Classic BPF program that picks the receiving socket in a SO_REUSEPORT group.
+///
+/// The program returns the index of the socket in the group (in bind order). If it returns an
+/// out-of-range index, the kernel falls back to its default hash-based selection.
+#[derive(Debug)]
+pub enum ReuseportSteering {
+    /// Deliver to the socket with the same index as the CPU that received the packet.
+    Cpu,
+    /// Deliver based on the kernel's flow hash of the packet (source/destination address and port).
+    FlowHash,
+    /// A custom program.
+    Custom(Vec<SockFilter>),
+}
+
+impl ReuseportSteering {
+    fn program(self, group_size: usize) -> Vec<SockFilter> {
+        const LD_ABS: u16 = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16;
+        const MOD_K: u16 = (libc::BPF_ALU | libc::BPF_MOD | libc::BPF_K) as u16;
+        const RET_A: u16 = (libc::BPF_RET | libc::BPF_A) as u16;
+
+        let ancillary = match self {
+            ReuseportSteering::Cpu => libc::SKF_AD_CPU,
+            ReuseportSteering::FlowHash => libc::SKF_AD_RXHASH,
+            ReuseportSteering::Custom(prog) => return prog,
+        };
+        vec![
+            SockFilter::new(LD_ABS, 0, 0, (libc::SKF_AD_OFF + ancillary) as u32),
+            SockFilter::new(MOD_K, 0, 0, group_size as u32),
+            SockFilter::new(RET_A, 0, 0, 0),
+        ]
+    }
+}
+
+/// A set of UDP sockets bound to the same address with SO_REUSEPORT, e.g. one per worker task.
+pub struct ReuseportUdpGroup {
+    sockets: Vec<UdpSocket>,
+}
+
+impl ReuseportUdpGroup {
+    /// Bind `count` sockets to `addr`. If the port is 0, all sockets share the port the first
+    /// one received. `opts.reuseport` is always enabled.
+    pub fn bind(
+        addr: SocketAddr,
+        count: usize,
+        opts: BindOpts,
+        steering: Option<ReuseportSteering>,
+    ) -> crate::Result<Self> {
+        if count == 0 {
+            return Err(Error::ReuseportGroupEmpty);
+        }
+        let opts = BindOpts {
+            reuseport: true,
+            ..opts
+        };
+
+        let first = UdpSocket::bind_udp(addr, opts)?;
+        let addr = first.bind_addr();
+        let mut sockets = Vec::with_capacity(count);
+        sockets.push(first);
+
+        // The rest of the group would trip the guard on each other.
+        let opts = BindOpts {
+            reuseport_guard: ReuseportGuard::Off,
+            ..opts
+        };
+        for _ in 1..count {
+            let sock = UdpSocket::bind_udp(addr, opts)?;
+            if sock.addr_kind() != sockets[0].addr_kind() {
+                debug!(first=?sockets[0].addr_kind(), this=?sock.addr_kind(), "reuseport group address mismatch");
+                return Err(Error::ReuseportGroupAddrMismatch);
+            }
+            sockets.push(sock);
+        }
+
+        if let Some(steering) = steering {
+            debug!(
+                ?steering,
+                ?addr,
+                count,
+                "attaching reuseport steering program"
+            );
+            attach_reuseport_cbpf(sockets[0].socket(), &steering.program(count))?;
+        }
+
+        debug!(?addr, count, "bound reuseport UDP group");
+        Ok(Self { sockets })
+    }
+
+    pub fn bind_addr(&self) -> SocketAddr {
+        self.sockets[0].bind_addr()
+    }
+
+    pub fn is_dualstack(&self) -> bool {
+        self.sockets[0].is_dualstack()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    pub fn sockets(&self) -> &[UdpSocket] {
+        &self.sockets
+    }
+
+    pub fn into_sockets(self) -> Vec<UdpSocket> {
+        self.sockets
+    }
+}
+
+impl IntoIterator for ReuseportUdpGroup {
+    type Item = UdpSocket;
+    type IntoIter = std::vec::IntoIter<UdpSocket>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.sockets.into_iter()
+    }
+}
+
+// The program is attached to the whole group, so it's enough to do it through one socket.
+fn attach_reuseport_cbpf(sock: &tokio::net::UdpSocket, prog: &[SockFilter]) -> crate::Result<()> {
+    let fprog = libc::sock_fprog {
+        len: prog.len() as u16,
+        // SockFilter is repr(transparent) over sock_filter.
+        filter: prog.as_ptr() as *mut libc::sock_filter,
+    };
+    crate::sockopt::set(
+        sock,
+        libc::SOL_SOCKET,
+        libc::SO_ATTACH_REUSEPORT_CBPF,
+        fprog,
+    )
+    .map_err(Error::AttachReuseportCbpf)
+}
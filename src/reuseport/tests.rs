@@ -0,0 +1,109 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use socket2::SockFilter;
+use tokio::time::timeout;
+
+use crate::{BindOpts, ReuseportSteering, ReuseportUdpGroup, UdpSocket};
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+async fn recv_counts(group: &ReuseportUdpGroup, expected_total: usize) -> Vec<usize> {
+    let mut counts = vec![0; group.len()];
+    let mut total = 0;
+    while total < expected_total {
+        let futs = group.sockets().iter().enumerate().map(|(idx, sock)| {
+            Box::pin(async move {
+                let mut buf = [0u8; 16];
+                let (_, addr) = sock.recv_from(&mut buf).await.unwrap();
+                (idx, addr)
+            })
+        });
+        let ((idx, addr), _, _) = timeout(TIMEOUT, futures::future::select_all(futs))
+            .await
+            .expect("timeout receiving");
+        assert_eq!(
+            addr.ip(),
+            Ipv4Addr::LOCALHOST,
+            "expected canonical v4 address"
+        );
+        counts[idx] += 1;
+        total += 1;
+    }
+    counts
+}
+
+#[tokio::test]
+async fn test_group_shares_addr() {
+    let group = ReuseportUdpGroup::bind(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        4,
+        BindOpts::default(),
+        Some(ReuseportSteering::Cpu),
+    )
+    .unwrap();
+    assert_eq!(group.len(), 4);
+    assert!(group.is_dualstack());
+    let kind = group.sockets()[0].addr_kind();
+    assert!(group.sockets().iter().all(|s| s.addr_kind() == kind));
+    assert_ne!(group.bind_addr().port(), 0);
+}
+
+#[tokio::test]
+async fn test_group_custom_steering() {
+    // Always deliver to socket 2.
+    let prog = vec![SockFilter::new(
+        (libc::BPF_RET | libc::BPF_K) as u16,
+        0,
+        0,
+        2,
+    )];
+    let group = ReuseportUdpGroup::bind(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        4,
+        BindOpts::default(),
+        Some(ReuseportSteering::Custom(prog)),
+    )
+    .unwrap();
+
+    let client = UdpSocket::bind_udp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let target = SocketAddr::from((Ipv4Addr::LOCALHOST, group.bind_addr().port()));
+    for _ in 0..10 {
+        client.send_to(b"hello", target).await.unwrap();
+    }
+
+    assert_eq!(recv_counts(&group, 10).await, vec![0, 0, 10, 0]);
+}
+
+#[tokio::test]
+async fn test_group_receives_everything() {
+    let group = ReuseportUdpGroup::bind(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        3,
+        BindOpts::default(),
+        Some(ReuseportSteering::FlowHash),
+    )
+    .unwrap();
+
+    let target = SocketAddr::from((Ipv4Addr::LOCALHOST, group.bind_addr().port()));
+    for _ in 0..10 {
+        let client =
+            UdpSocket::bind_udp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+        client.send_to(b"hello", target).await.unwrap();
+    }
+
+    assert_eq!(recv_counts(&group, 10).await.iter().sum::<usize>(), 10);
+}
+
+#[tokio::test]
+async fn test_group_empty() {
+    assert!(
+        ReuseportUdpGroup::bind(
+            (Ipv4Addr::LOCALHOST, 0).into(),
+            0,
+            BindOpts::default(),
+            None
+        )
+        .is_err()
+    );
+}
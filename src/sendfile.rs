@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod tests;
+
+use std::{fs::File, os::fd::AsRawFd, os::unix::fs::FileExt};
+
+use tokio::{io::Interest, net::TcpStream};
+use tracing::{debug, trace};
+
+// sendfile() transfers at most this much per call.
+const MAX_SENDFILE_CHUNK: u64 = 0x7fff_f000;
+const COPY_BUF_LEN: usize = 64 * 1024;
+
+/// Send `len` bytes of `file` starting at `offset` to the stream with sendfile(2), without
+/// copying them through userspace. Falls back to a buffered copy for files sendfile() doesn't
+/// support (e.g. most of procfs). Fails with UnexpectedEof if the file ends before the range does.
+///
+/// Reading the file blocks the runtime thread, same as with std::fs::File.
+pub async fn tcp_send_file(
+    stream: &TcpStream,
+    file: &File,
+    offset: u64,
+    len: u64,
+) -> std::io::Result<()> {
+    let end = offset
+        .checked_add(len)
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+    let mut pos = offset;
+    while pos < end {
+        let res = stream
+            .async_io(Interest::WRITABLE, || {
+                sendfile(stream, file, pos, (end - pos).min(MAX_SENDFILE_CHUNK))
+            })
+            .await;
+        match res {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(sz) => {
+                trace!(pos, sz, "sendfile");
+                pos += sz;
+            }
+            // Only returned before anything is sent, if the file doesn't support it.
+            Err(e) if is_unsupported(&e) => {
+                debug!("sendfile not supported, falling back to copying: {e:#}");
+                return copy_range(stream, file, pos, end).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn sendfile(stream: &TcpStream, file: &File, pos: u64, count: u64) -> std::io::Result<u64> {
+    let mut off = pos as libc::off_t;
+    let sz = unsafe {
+        libc::sendfile(
+            stream.as_raw_fd(),
+            file.as_raw_fd(),
+            &mut off,
+            count as usize,
+        )
+    };
+    if sz < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(sz as u64)
+}
+
+fn is_unsupported(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP)
+    )
+}
+
+async fn copy_range(
+    stream: &TcpStream,
+    file: &File,
+    mut pos: u64,
+    end: u64,
+) -> std::io::Result<()> {
+    let mut buf = vec![0u8; COPY_BUF_LEN];
+    while pos < end {
+        let want = (end - pos).min(buf.len() as u64) as usize;
+        let sz = file.read_at(&mut buf[..want], pos)?;
+        if sz == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut chunk = &buf[..sz];
+        while !chunk.is_empty() {
+            let written = stream
+                .async_io(Interest::WRITABLE, || stream.try_write(chunk))
+                .await?;
+            chunk = &chunk[written..];
+        }
+        pos += sz as u64;
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::io::Write;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::{io::AsyncReadExt, net::TcpStream, time::timeout};
+
+use crate::{BindOpts, TcpListener, tcp_send_file};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn connected_pair(listen: SocketAddr) -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind_tcp(listen, BindOpts::default()).unwrap();
+    let port = listener.bind_addr().port();
+    let (client, accepted) = tokio::join!(
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)),
+        listener.accept()
+    );
+    (client.unwrap(), accepted.unwrap().0)
+}
+
+fn temp_file(name: &str, data: &[u8]) -> (std::path::PathBuf, std::fs::File) {
+    let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(data)
+        .unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    (path, file)
+}
+
+async fn send_and_receive(file: &std::fs::File, offset: u64, len: u64) -> Vec<u8> {
+    let (mut client, accepted) = connected_pair((Ipv6Addr::UNSPECIFIED, 0).into()).await;
+    let reader = tokio::spawn(async move {
+        let mut buf = vec![0u8; len as usize];
+        client.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    timeout(TIMEOUT, tcp_send_file(&accepted, file, offset, len))
+        .await
+        .unwrap()
+        .unwrap();
+    timeout(TIMEOUT, reader).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn test_send_file_range() {
+    // Bigger than the socket buffers, so sendfile() gets partial writes and EAGAIN.
+    let data: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+    let (path, file) = temp_file("sendfile-range", &data);
+
+    let received = send_and_receive(&file, 0, data.len() as u64).await;
+    assert!(received == data);
+
+    let received = send_and_receive(&file, 12345, 1_000_000).await;
+    assert!(received == data[12345..12345 + 1_000_000]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_send_file_past_eof() {
+    let (path, file) = temp_file("sendfile-eof", b"hello");
+    let (_client, accepted) = connected_pair((Ipv4Addr::LOCALHOST, 0).into()).await;
+    let err = tcp_send_file(&accepted, &file, 2, 10).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_send_file_fallback() {
+    // sendfile() fails with EINVAL for most procfs files.
+    let file = std::fs::File::open("/proc/self/cmdline").unwrap();
+    let expected = std::fs::read("/proc/self/cmdline").unwrap();
+    let received = send_and_receive(&file, 1, expected.len() as u64 - 1).await;
+    assert_eq!(received, expected[1..]);
+}
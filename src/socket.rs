@@ -10,9 +10,11 @@ use crate::{
     Error,
     addr::{ToV6Mapped, TryToV4},
     bind_device::BindDevice,
+    pmtu::MtuDiscover,
+    port_guard::ReuseportGuard,
 };
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SocketAddrKind {
     V4(SocketAddrV4),
     V6 {
@@ -39,6 +41,19 @@ pub struct BindOpts<'a> {
     pub request_dualstack: bool,
     pub reuseport: bool,
     pub device: Option<&'a BindDevice>,
+    /// UDP only: path MTU discovery mode. Set for both IPv4 and IPv6 on dualstack sockets.
+    pub mtu_discover: Option<MtuDiscover>,
+    /// Check if other sockets share our port after binding (see [`ReuseportGuard`]).
+    pub reuseport_guard: ReuseportGuard,
+    /// TCP only: enable TCP Fast Open on the listener with this queue length (TCP_FASTOPEN).
+    pub tcp_fastopen: Option<u32>,
+    /// TCP only: use Multipath TCP if the kernel supports it, plain TCP otherwise.
+    pub mptcp: bool,
+    /// Linux only: firewall mark for policy routing (SO_MARK). Requires CAP_NET_ADMIN.
+    pub fwmark: Option<u32>,
+    /// TCP only, Linux only: congestion control algorithm (TCP_CONGESTION), e.g. "bbr". Accepted
+    /// streams inherit it.
+    pub tcp_congestion: Option<&'a str>,
 }
 
 impl Default for BindOpts<'_> {
@@ -47,6 +62,12 @@ impl Default for BindOpts<'_> {
             request_dualstack: true,
             reuseport: false,
             device: None,
+            mtu_discover: None,
+            reuseport_guard: ReuseportGuard::Off,
+            tcp_fastopen: None,
+            mptcp: false,
+            fwmark: None,
+            tcp_congestion: None,
         }
     }
 }
@@ -65,6 +86,10 @@ impl<S> MaybeDualstackSocket<S> {
         self.addr_kind.as_socketaddr()
     }
 
+    pub fn addr_kind(&self) -> SocketAddrKind {
+        self.addr_kind
+    }
+
     pub fn is_dualstack(&self) -> bool {
         matches!(
             self.addr_kind,
@@ -85,24 +110,17 @@ impl<S> MaybeDualstackSocket<S> {
 
 impl MaybeDualstackSocket<Socket> {
     fn bind(addr: SocketAddr, opts: BindOpts, is_udp: bool) -> crate::Result<Self> {
-        let socket = Socket::new(
-            if addr.is_ipv6() {
-                Domain::IPV6
-            } else {
-                Domain::IPV4
-            },
-            if is_udp {
-                socket2::Type::DGRAM
-            } else {
-                socket2::Type::STREAM
-            },
-            Some(if is_udp {
-                socket2::Protocol::UDP
-            } else {
-                socket2::Protocol::TCP
-            }),
-        )
-        .map_err(Error::SocketNew)?;
+        let domain = if addr.is_ipv6() {
+            Domain::IPV6
+        } else {
+            Domain::IPV4
+        };
+        let socket = if is_udp {
+            Socket::new(domain, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))
+                .map_err(Error::SocketNew)?
+        } else {
+            crate::mptcp::tcp_socket(domain, opts.mptcp)?
+        };
 
         let mut set_dualstack = false;
 
@@ -162,6 +180,28 @@ impl MaybeDualstackSocket<Socket> {
             bd.bind_sref(&socket, addr_kind.is_v6())?;
         }
 
+        if let Some(mark) = opts.fwmark {
+            crate::fwmark::set_fwmark(&socket, mark)?;
+        }
+
+        if let Some(mode) = opts.mtu_discover
+            && is_udp
+        {
+            crate::pmtu::set_mtu_discover(&socket, mode, addr_kind)?;
+        }
+
+        if let Some(qlen) = opts.tcp_fastopen
+            && !is_udp
+        {
+            crate::fastopen::set_listener_qlen(&socket, qlen)?;
+        }
+
+        if let Some(name) = opts.tcp_congestion
+            && !is_udp
+        {
+            crate::congestion::set_tcp_congestion(&socket, name)?;
+        }
+
         socket.bind(&addr.into()).map_err(|e| {
             trace!(?addr, "error binding: {e:#}");
             Error::Bind(e)
@@ -187,6 +227,21 @@ impl MaybeDualstackSocket<Socket> {
             }
         };
 
+        let is_dualstack = matches!(
+            addr_kind,
+            SocketAddrKind::V6 {
+                is_dualstack: true,
+                ..
+            }
+        );
+        crate::port_guard::check(
+            opts.reuseport_guard,
+            &socket,
+            local_addr,
+            is_dualstack,
+            is_udp,
+        )?;
+
         socket
             .set_nonblocking(true)
             .map_err(Error::SetNonblocking)?;
@@ -263,6 +318,18 @@ impl MaybeDualstackSocket<tokio::net::TcpListener> {
         })
     }
 
+    /// Whether the listener is a Multipath TCP socket. Check each accepted stream with
+    /// [`TcpStreamExt::is_mptcp`](crate::TcpStreamExt::is_mptcp), as peers may not support it.
+    #[cfg(target_os = "linux")]
+    pub fn is_mptcp(&self) -> bool {
+        crate::mptcp::is_mptcp(&self.socket).unwrap_or(false)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn is_mptcp(&self) -> bool {
+        false
+    }
+
     pub async fn accept(&self) -> std::io::Result<(tokio::net::TcpStream, SocketAddr)> {
         let (s, addr) = self.socket.accept().await?;
         Ok((s, addr.try_to_ipv4()))
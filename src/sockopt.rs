@@ -0,0 +1,69 @@
+//! Thin wrappers around setsockopt()/getsockopt() for options that socket2 doesn't expose.
+
+use std::os::fd::{AsFd, AsRawFd};
+
+pub(crate) fn set<T: Copy>(
+    fd: &impl AsFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: T,
+) -> std::io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd.as_fd().as_raw_fd(),
+            level,
+            name,
+            (&value as *const T).cast(),
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn get<T: Copy + Default>(
+    fd: &impl AsFd,
+    level: libc::c_int,
+    name: libc::c_int,
+) -> std::io::Result<T> {
+    let mut value = T::default();
+    let mut len = std::mem::size_of::<T>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd.as_fd().as_raw_fd(),
+            level,
+            name,
+            (&mut value as *mut T).cast(),
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// getsockopt() into a byte buffer, returning how many bytes the kernel filled in.
+pub(crate) fn get_buf(
+    fd: &impl AsFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut len = buf.len() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd.as_fd().as_raw_fd(),
+            level,
+            name,
+            buf.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(len as usize)
+}
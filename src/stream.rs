@@ -0,0 +1,87 @@
+/// Extra information and options of connected TCP streams.
+pub trait TcpStreamExt {
+    /// Whether the connection uses Multipath TCP. False if MPTCP was requested but the peer or
+    /// the kernel don't support it.
+    fn is_mptcp(&self) -> std::io::Result<bool>;
+
+    /// Linux only: the firewall mark (SO_MARK). Accepted streams inherit it from the listener.
+    fn fwmark(&self) -> std::io::Result<u32>;
+
+    /// Linux only: the congestion control algorithm (TCP_CONGESTION).
+    fn tcp_congestion(&self) -> std::io::Result<String>;
+
+    /// Linux only: limit the sending rate in bytes per second (SO_MAX_PACING_RATE). None means
+    /// unlimited. Can be changed at any time.
+    fn set_max_pacing_rate(&self, rate: Option<u64>) -> std::io::Result<()>;
+
+    /// Linux only: the pacing rate limit, None if unlimited.
+    fn max_pacing_rate(&self) -> std::io::Result<Option<u64>>;
+
+    /// Linux only: RTT, congestion window, delivery rate etc. (TCP_INFO). See
+    /// [`TcpInfoSampler`](crate::TcpInfoSampler) for per-interval deltas.
+    fn tcp_info(&self) -> std::io::Result<crate::TcpInfo>;
+}
+
+impl TcpStreamExt for tokio::net::TcpStream {
+    #[cfg(target_os = "linux")]
+    fn is_mptcp(&self) -> std::io::Result<bool> {
+        crate::mptcp::is_mptcp(self)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn is_mptcp(&self) -> std::io::Result<bool> {
+        Ok(false)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fwmark(&self) -> std::io::Result<u32> {
+        socket2::SockRef::from(self).mark()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn fwmark(&self) -> std::io::Result<u32> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tcp_congestion(&self) -> std::io::Result<String> {
+        let name = socket2::SockRef::from(self).tcp_congestion()?;
+        let name = name.split(|b| *b == 0).next().unwrap_or_default();
+        Ok(String::from_utf8_lossy(name).into_owned())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn tcp_congestion(&self) -> std::io::Result<String> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_max_pacing_rate(&self, rate: Option<u64>) -> std::io::Result<()> {
+        crate::pacing::set_max_pacing_rate(self, rate)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_max_pacing_rate(&self, _rate: Option<u64>) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn max_pacing_rate(&self) -> std::io::Result<Option<u64>> {
+        crate::pacing::max_pacing_rate(self)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn max_pacing_rate(&self) -> std::io::Result<Option<u64>> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tcp_info(&self) -> std::io::Result<crate::TcpInfo> {
+        crate::tcp_info::get(self)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn tcp_info(&self) -> std::io::Result<crate::TcpInfo> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+}
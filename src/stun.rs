@@ -0,0 +1,245 @@
+#[cfg(test)]
+mod tests;
+
+pub(crate) mod codec;
+
+use std::{collections::HashMap, net::SocketAddr, sync::Mutex, time::Duration};
+
+use futures::{StreamExt, stream::FuturesUnordered};
+use tokio::sync::oneshot;
+use tracing::{debug, trace};
+
+use crate::{Error, UdpSocket, addr::TryToV4};
+
+use codec::{Message, MessageBuilder, TransactionId};
+
+/// Retransmission timers (RFC 5389 section 7.2.1).
+#[derive(Clone, Copy, Debug)]
+pub struct StunOpts {
+    /// Initial retransmission timeout, doubled after each retransmit.
+    pub rto: Duration,
+    /// How many times to send a request in total. After the last one, the client waits for
+    /// 16 * `rto`.
+    pub max_requests: u32,
+}
+
+impl Default for StunOpts {
+    fn default() -> Self {
+        Self {
+            rto: Duration::from_millis(500),
+            max_requests: 7,
+        }
+    }
+}
+
+/// The result of a STUN binding request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StunBinding {
+    pub server: SocketAddr,
+    /// Our address as seen by the server (XOR-MAPPED-ADDRESS).
+    pub mapped_addr: SocketAddr,
+    /// The server's alternate address, if it supports RFC 5780 (OTHER-ADDRESS).
+    pub other_addr: Option<SocketAddr>,
+}
+
+/// NAT mapping behavior (RFC 5780 section 4.3).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NatMapping {
+    /// The mapped address is our own address.
+    NoNat,
+    /// The same mapping is reused for all destinations. Hole punching works.
+    EndpointIndependent,
+    /// The mapping changes with the destination IP.
+    AddressDependent,
+    /// The mapping changes with the destination IP and port.
+    AddressAndPortDependent,
+}
+
+struct Transaction {
+    server: SocketAddr,
+    tx: oneshot::Sender<Vec<u8>>,
+}
+
+/// STUN client (RFC 5389) that runs on an existing [`UdpSocket`], sharing it with other
+/// protocols.
+///
+/// The client doesn't read from the socket. Pass every received datagram to
+/// [`handle_datagram`](Self::handle_datagram), which consumes the responses to its own requests.
+#[derive(Default)]
+pub struct StunClient {
+    opts: StunOpts,
+    transactions: Mutex<HashMap<TransactionId, Transaction>>,
+}
+
+// Removes the transaction when the request finishes or is cancelled.
+struct TransactionGuard<'a> {
+    client: &'a StunClient,
+    txid: TransactionId,
+}
+
+impl Drop for TransactionGuard<'_> {
+    fn drop(&mut self) {
+        self.client.transactions.lock().unwrap().remove(&self.txid);
+    }
+}
+
+impl StunClient {
+    pub fn new(opts: StunOpts) -> Self {
+        Self {
+            opts,
+            transactions: Default::default(),
+        }
+    }
+
+    /// Feed a datagram received on the socket. Returns true if it was a response to one of our
+    /// requests, and so isn't meant for anyone else.
+    pub fn handle_datagram(&self, buf: &[u8], from: SocketAddr) -> bool {
+        let Some(msg) = Message::parse(buf).filter(|m| m.is_response()) else {
+            return false;
+        };
+        let from = from.try_to_ipv4();
+        let mut transactions = self.transactions.lock().unwrap();
+        match transactions.get(&msg.txid) {
+            Some(t) if t.server == from => {}
+            Some(t) => {
+                debug!(?from, server=?t.server, "STUN response from an unexpected address");
+                return false;
+            }
+            None => return false,
+        }
+        if let Some(t) = transactions.remove(&msg.txid) {
+            trace!(?from, "STUN response");
+            let _ = t.tx.send(buf.to_vec());
+        }
+        true
+    }
+
+    /// Send a binding request to `server` and wait for the response, retransmitting as needed.
+    pub async fn binding(
+        &self,
+        sock: &UdpSocket,
+        server: SocketAddr,
+    ) -> crate::Result<StunBinding> {
+        let server = server.try_to_ipv4();
+        let txid = codec::new_transaction_id();
+        let request = MessageBuilder::new(codec::BINDING_REQUEST, txid).finish();
+        let response = self.transaction(sock, server, txid, &request).await?;
+        let msg = Message::parse(&response).ok_or(Error::StunInvalidResponse)?;
+        if msg.method() != codec::BINDING_REQUEST {
+            return Err(Error::StunInvalidResponse);
+        }
+        if msg.is_error() {
+            let (code, reason) = msg.error_code().ok_or(Error::StunInvalidResponse)?;
+            return Err(Error::StunErrorResponse { code, reason });
+        }
+        let mapped_addr = msg.mapped_addr().ok_or(Error::StunInvalidResponse)?;
+        let binding = StunBinding {
+            server,
+            mapped_addr,
+            other_addr: msg.addr(codec::ATTR_OTHER_ADDRESS),
+        };
+        debug!(?server, ?mapped_addr, "STUN binding");
+        Ok(binding)
+    }
+
+    /// Query all `servers` at once and return the first successful binding.
+    pub async fn external_addr(
+        &self,
+        sock: &UdpSocket,
+        servers: &[SocketAddr],
+    ) -> crate::Result<StunBinding> {
+        let mut requests: FuturesUnordered<_> =
+            servers.iter().map(|s| self.binding(sock, *s)).collect();
+        let mut last_error = None;
+        while let Some(res) = requests.next().await {
+            match res {
+                Ok(binding) => return Ok(binding),
+                Err(e) => {
+                    debug!("STUN binding failed: {e:#}");
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(Error::NoAddresses))
+    }
+
+    /// Determine the NAT mapping behavior with a server that supports RFC 5780, by comparing the
+    /// mapped addresses reported for its primary and alternate addresses.
+    pub async fn nat_mapping(
+        &self,
+        sock: &UdpSocket,
+        server: SocketAddr,
+    ) -> crate::Result<NatMapping> {
+        let first = self.binding(sock, server).await?;
+        if sock.bind_addr().port() == first.mapped_addr.port()
+            && is_local_ip(sock, first.mapped_addr.ip())
+        {
+            return Ok(NatMapping::NoNat);
+        }
+        let other = first.other_addr.ok_or(Error::StunNoOtherAddress)?;
+
+        // Alternate IP, primary port.
+        let second = self
+            .binding(sock, SocketAddr::new(other.ip(), first.server.port()))
+            .await?;
+        if second.mapped_addr == first.mapped_addr {
+            return Ok(NatMapping::EndpointIndependent);
+        }
+
+        // Alternate IP and port.
+        let third = self.binding(sock, other).await?;
+        let mapping = if third.mapped_addr == second.mapped_addr {
+            NatMapping::AddressDependent
+        } else {
+            NatMapping::AddressAndPortDependent
+        };
+        debug!(?server, ?mapping, "NAT mapping behavior");
+        Ok(mapping)
+    }
+
+    pub(crate) async fn transaction(
+        &self,
+        sock: &UdpSocket,
+        server: SocketAddr,
+        txid: TransactionId,
+        request: &[u8],
+    ) -> crate::Result<Vec<u8>> {
+        let (tx, mut rx) = oneshot::channel();
+        self.transactions
+            .lock()
+            .unwrap()
+            .insert(txid, Transaction { server, tx });
+        let _guard = TransactionGuard { client: self, txid };
+
+        let mut rto = self.opts.rto;
+        for i in 0..self.opts.max_requests {
+            trace!(?server, attempt = i, "sending STUN request");
+            sock.send_to(request, server).await.map_err(Error::Stun)?;
+            let wait = if i + 1 == self.opts.max_requests {
+                self.opts.rto.saturating_mul(16)
+            } else {
+                rto
+            };
+            if let Ok(response) = tokio::time::timeout(wait, &mut rx).await {
+                return response.map_err(|_| Error::StunInvalidResponse);
+            }
+            rto = rto.saturating_mul(2);
+        }
+        Err(Error::StunTimeout { server })
+    }
+}
+
+fn is_local_ip(sock: &UdpSocket, ip: std::net::IpAddr) -> bool {
+    let bound = sock.bind_addr().ip().to_canonical();
+    if !bound.is_unspecified() {
+        return bound == ip;
+    }
+    use network_interface::NetworkInterfaceConfig;
+    network_interface::NetworkInterface::show()
+        .map(|nics| {
+            nics.iter()
+                .flat_map(|nic| nic.addr.iter())
+                .any(|a| a.ip() == ip)
+        })
+        .unwrap_or(false)
+}
@@ -0,0 +1,302 @@
+//! STUN message encoding and decoding (RFC 5389).
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::addr::TryToV4;
+
+pub(crate) const HEADER_LEN: usize = 20;
+pub(crate) const MAGIC_COOKIE: u32 = 0x2112_a442;
+
+pub(crate) const BINDING_REQUEST: u16 = 0x0001;
+// Responses are only built by the test server.
+#[cfg(test)]
+pub(crate) const BINDING_SUCCESS: u16 = 0x0101;
+#[cfg(test)]
+pub(crate) const BINDING_ERROR: u16 = 0x0111;
+
+pub(crate) const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+pub(crate) const ATTR_ERROR_CODE: u16 = 0x0009;
+pub(crate) const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+pub(crate) const ATTR_OTHER_ADDRESS: u16 = 0x802c;
+pub(crate) const ATTR_USERNAME: u16 = 0x0006;
+pub(crate) const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+pub(crate) const ATTR_REALM: u16 = 0x0014;
+pub(crate) const ATTR_NONCE: u16 = 0x0015;
+
+const MESSAGE_INTEGRITY_LEN: usize = 20;
+
+const FAMILY_V4: u8 = 0x01;
+const FAMILY_V6: u8 = 0x02;
+
+// Message class bits of the message type.
+const CLASS_MASK: u16 = 0x0110;
+const CLASS_SUCCESS: u16 = 0x0100;
+const CLASS_ERROR: u16 = 0x0110;
+
+pub(crate) type TransactionId = [u8; 12];
+
+/// A random transaction id. Not cryptographically strong, just unpredictable enough that off-path
+/// responses don't match.
+pub(crate) fn new_transaction_id() -> TransactionId {
+    use std::hash::{BuildHasher, Hasher};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut id = [0u8; 12];
+    for chunk in id.chunks_mut(6) {
+        let mut h = std::collections::hash_map::RandomState::new().build_hasher();
+        h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        chunk.copy_from_slice(&h.finish().to_ne_bytes()[..6]);
+    }
+    id
+}
+
+/// Whether the first bytes look like a STUN message, to tell it apart from other protocols
+/// multiplexed on the same socket (RFC 5389 section 8).
+/// Classic (RFC 3489) servers pass this too when answering our requests: they echo the 128-bit
+/// transaction id, the first 32 bits of which are the magic cookie.
+pub(crate) fn is_stun(buf: &[u8]) -> bool {
+    buf.len() >= HEADER_LEN
+        && buf[0] & 0xc0 == 0
+        && u32::from_be_bytes(buf[4..8].try_into().unwrap()) == MAGIC_COOKIE
+}
+
+pub(crate) struct MessageBuilder {
+    buf: Vec<u8>,
+    txid: TransactionId,
+}
+
+impl MessageBuilder {
+    pub fn new(msg_type: u16, txid: TransactionId) -> Self {
+        let mut buf = Vec::with_capacity(128);
+        buf.extend_from_slice(&msg_type.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf.extend_from_slice(&txid);
+        Self { buf, txid }
+    }
+
+    pub fn attr(&mut self, ty: u16, value: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(&ty.to_be_bytes());
+        self.buf
+            .extend_from_slice(&(value.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(value);
+        let padding = (4 - value.len() % 4) % 4;
+        self.buf.extend_from_slice(&[0u8; 3][..padding]);
+        let len = (self.buf.len() - HEADER_LEN) as u16;
+        self.buf[2..4].copy_from_slice(&len.to_be_bytes());
+        self
+    }
+
+    pub fn xor_addr(&mut self, ty: u16, addr: SocketAddr) -> &mut Self {
+        let value = encode_addr(addr.try_to_ipv4(), Some(&self.txid));
+        self.attr(ty, &value)
+    }
+
+    /// Append MESSAGE-INTEGRITY (HMAC-SHA1 with the long-term credential key). Must be the last
+    /// attribute.
+    pub fn integrity(&mut self, key: &[u8]) -> &mut Self {
+        let len = self.buf.len() + 4 + MESSAGE_INTEGRITY_LEN;
+        self.buf[2..4].copy_from_slice(&((len - HEADER_LEN) as u16).to_be_bytes());
+        let mac = hmac_sha1(key, &self.buf);
+        self.attr(ATTR_MESSAGE_INTEGRITY, &mac)
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+// Only the test servers send these.
+#[cfg(test)]
+impl MessageBuilder {
+    pub fn addr(&mut self, ty: u16, addr: SocketAddr) -> &mut Self {
+        let value = encode_addr(addr.try_to_ipv4(), None);
+        self.attr(ty, &value)
+    }
+
+    pub fn error_code(&mut self, code: u16, reason: &str) -> &mut Self {
+        let mut value = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+        value.extend_from_slice(reason.as_bytes());
+        self.attr(ATTR_ERROR_CODE, &value)
+    }
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; MESSAGE_INTEGRITY_LEN] {
+    use hmac::Mac;
+    let mut mac = hmac::Hmac::<sha1::Sha1>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// The long-term credential key (RFC 5389 section 15.4). The password isn't SASLprep'ed.
+pub(crate) fn long_term_key(username: &str, realm: &str, password: &str) -> [u8; 16] {
+    use md5::Digest;
+    md5::Md5::digest(format!("{username}:{realm}:{password}")).into()
+}
+
+fn xor_key(txid: &TransactionId) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    key[4..].copy_from_slice(txid);
+    key
+}
+
+fn encode_addr(addr: SocketAddr, xor: Option<&TransactionId>) -> Vec<u8> {
+    let key = xor.map(xor_key).unwrap_or_default();
+    let port = addr.port() ^ u16::from_be_bytes([key[0], key[1]]);
+    let (family, ip) = match addr.ip() {
+        IpAddr::V4(ip) => (FAMILY_V4, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (FAMILY_V6, ip.octets().to_vec()),
+    };
+    let mut value = vec![0, family];
+    value.extend_from_slice(&port.to_be_bytes());
+    value.extend(ip.iter().zip(key).map(|(b, k)| b ^ k));
+    value
+}
+
+fn decode_addr(value: &[u8], xor: Option<&TransactionId>) -> Option<SocketAddr> {
+    let key = xor.map(xor_key).unwrap_or_default();
+    let port = u16::from_be_bytes(value.get(2..4)?.try_into().ok()?)
+        ^ u16::from_be_bytes([key[0], key[1]]);
+    let mut ip = [0u8; 16];
+    let ip = match *value.get(1)? {
+        FAMILY_V4 => {
+            let raw = value.get(4..8)?;
+            for (i, (b, k)) in raw.iter().zip(key).enumerate() {
+                ip[i] = b ^ k;
+            }
+            IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]))
+        }
+        FAMILY_V6 => {
+            let raw = value.get(4..20)?;
+            for (i, (b, k)) in raw.iter().zip(key).enumerate() {
+                ip[i] = b ^ k;
+            }
+            IpAddr::V6(Ipv6Addr::from(ip))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port).try_to_ipv4())
+}
+
+pub(crate) struct Message<'a> {
+    pub msg_type: u16,
+    pub txid: TransactionId,
+    /// (type, value, offset of the attribute header)
+    attrs: Vec<(u16, &'a [u8], usize)>,
+    raw: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if !is_stun(buf) {
+            return None;
+        }
+        let msg_type = u16::from_be_bytes([buf[0], buf[1]]);
+        let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        let raw = buf.get(..HEADER_LEN + len)?;
+        let txid = raw[8..HEADER_LEN].try_into().unwrap();
+        let mut attrs = Vec::new();
+        let mut pos = HEADER_LEN;
+        while pos < raw.len() {
+            let ty = u16::from_be_bytes(raw.get(pos..pos + 2)?.try_into().ok()?);
+            let len = u16::from_be_bytes(raw.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+            let value = raw.get(pos + 4..pos + 4 + len)?;
+            attrs.push((ty, value, pos));
+            pos += 4 + len.div_ceil(4) * 4;
+        }
+        Some(Self {
+            msg_type,
+            txid,
+            attrs,
+            raw,
+        })
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.msg_type & CLASS_MASK == CLASS_SUCCESS
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.msg_type & CLASS_MASK == CLASS_ERROR
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.is_success() || self.is_error()
+    }
+
+    /// The method, with the class bits cleared.
+    pub fn method(&self) -> u16 {
+        self.msg_type & !CLASS_MASK
+    }
+
+    pub fn attr(&self, ty: u16) -> Option<&'a [u8]> {
+        self.attrs
+            .iter()
+            .find(|(t, ..)| *t == ty)
+            .map(|(_, value, _)| *value)
+    }
+
+    pub fn string(&self, ty: u16) -> Option<String> {
+        self.attr(ty)
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    }
+
+    pub fn u32(&self, ty: u16) -> Option<u32> {
+        Some(u32::from_be_bytes(
+            self.attr(ty)?.get(..4)?.try_into().ok()?,
+        ))
+    }
+
+    /// All attributes of type `ty` decoded as XOR addresses.
+    #[cfg(test)]
+    pub fn xor_addrs(&self, ty: u16) -> Vec<SocketAddr> {
+        self.attrs
+            .iter()
+            .filter(|(t, ..)| *t == ty)
+            .filter_map(|(_, value, _)| decode_addr(value, Some(&self.txid)))
+            .collect()
+    }
+
+    /// Whether MESSAGE-INTEGRITY is present and matches `key`.
+    pub fn check_integrity(&self, key: &[u8]) -> bool {
+        use hmac::Mac;
+        let Some((_, value, offset)) = self
+            .attrs
+            .iter()
+            .find(|(t, ..)| *t == ATTR_MESSAGE_INTEGRITY)
+        else {
+            return false;
+        };
+        let mut covered = self.raw[..*offset].to_vec();
+        let len = offset + 4 + MESSAGE_INTEGRITY_LEN - HEADER_LEN;
+        covered[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        let mut mac =
+            hmac::Hmac::<sha1::Sha1>::new_from_slice(key).expect("HMAC takes any key length");
+        mac.update(&covered);
+        mac.verify_slice(value).is_ok()
+    }
+
+    pub fn addr(&self, ty: u16) -> Option<SocketAddr> {
+        decode_addr(self.attr(ty)?, None)
+    }
+
+    pub fn xor_addr(&self, ty: u16) -> Option<SocketAddr> {
+        decode_addr(self.attr(ty)?, Some(&self.txid))
+    }
+
+    /// XOR-MAPPED-ADDRESS, or MAPPED-ADDRESS from old (RFC 3489) servers.
+    pub fn mapped_addr(&self) -> Option<SocketAddr> {
+        self.xor_addr(ATTR_XOR_MAPPED_ADDRESS)
+            .or_else(|| self.addr(ATTR_MAPPED_ADDRESS))
+    }
+
+    pub fn error_code(&self) -> Option<(u16, String)> {
+        let value = self.attr(ATTR_ERROR_CODE)?;
+        let class = *value.get(2)? as u16 & 0x07;
+        let number = *value.get(3)? as u16;
+        let reason = String::from_utf8_lossy(&value[4..]).into_owned();
+        Some((class * 100 + number, reason))
+    }
+}
@@ -0,0 +1,497 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use std::time::{Duration, Instant};
+
+use tokio::{sync::mpsc, task::JoinHandle, time::timeout};
+
+use super::codec::{self, Message, MessageBuilder};
+use crate::{BindOpts, Error, NatMapping, StunClient, StunOpts, UdpSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// RFC 5769 section 2.2 and 2.3.
+const SAMPLE_RESPONSE_V4: &[u8] = &[
+    0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86,
+    0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76, 0x65, 0x63,
+    0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43,
+    0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99, 0xfd, 0x9e, 0x90, 0xc3, 0x8c, 0x74, 0x89, 0xf9,
+    0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b, 0xe7, 0xd7, 0x80, 0x28, 0x00, 0x04, 0xc0, 0x7d, 0x4c, 0x96,
+];
+const SAMPLE_RESPONSE_V6: &[u8] = &[
+    0x01, 0x01, 0x00, 0x48, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86,
+    0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76, 0x65, 0x63,
+    0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa,
+    0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9, 0x00, 0x08, 0x00, 0x14,
+    0xa3, 0x82, 0x95, 0x4e, 0x4b, 0xe6, 0x7b, 0xf1, 0x17, 0x84, 0xc9, 0x7c, 0x82, 0x92, 0xc2, 0x75,
+    0xbf, 0xe3, 0xed, 0x41, 0x80, 0x28, 0x00, 0x04, 0xc8, 0xfb, 0x0b, 0x4c,
+];
+
+#[test]
+fn test_codec_rfc5769() {
+    let msg = Message::parse(SAMPLE_RESPONSE_V4).unwrap();
+    assert!(msg.is_success());
+    assert_eq!(msg.method(), codec::BINDING_REQUEST);
+    assert_eq!(
+        msg.mapped_addr(),
+        Some((Ipv4Addr::new(192, 0, 2, 1), 32853).into())
+    );
+
+    let msg = Message::parse(SAMPLE_RESPONSE_V6).unwrap();
+    let ip: Ipv6Addr = "2001:db8:1234:5678:11:2233:4455:6677".parse().unwrap();
+    assert_eq!(msg.mapped_addr(), Some((ip, 32853).into()));
+}
+
+#[test]
+fn test_codec_roundtrip() {
+    let txid = codec::new_transaction_id();
+    assert_ne!(txid, codec::new_transaction_id());
+    let v4: SocketAddr = (Ipv4Addr::new(1, 2, 3, 4), 5678).into();
+    let v6: SocketAddr = (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 9).into();
+    let mapped: SocketAddr = (Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped(), 5678).into();
+    let mut b = MessageBuilder::new(codec::BINDING_ERROR, txid);
+    b.xor_addr(codec::ATTR_XOR_MAPPED_ADDRESS, mapped)
+        .addr(codec::ATTR_OTHER_ADDRESS, v6)
+        .error_code(438, "Stale Nonce");
+    let buf = b.finish();
+
+    let msg = Message::parse(&buf).unwrap();
+    assert!(msg.is_error() && msg.is_response());
+    assert_eq!(msg.txid, txid);
+    assert_eq!(msg.mapped_addr(), Some(v4));
+    assert_eq!(msg.addr(codec::ATTR_OTHER_ADDRESS), Some(v6));
+    assert_eq!(msg.error_code(), Some((438, "Stale Nonce".to_owned())));
+
+    assert!(!codec::is_stun(
+        b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe"
+    ));
+    assert!(Message::parse(&buf[..buf.len() - 1]).is_none());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FakeNat {
+    None,
+    EndpointIndependent,
+    AddressDependent,
+    AddressAndPortDependent,
+}
+
+#[derive(Clone, Copy)]
+struct ServerOpts {
+    nat: FakeNat,
+    other_address: bool,
+    drop_first: usize,
+    error: Option<u16>,
+    /// Answer like an RFC 3489 server, with MAPPED-ADDRESS.
+    classic: bool,
+}
+
+impl Default for ServerOpts {
+    fn default() -> Self {
+        Self {
+            nat: FakeNat::None,
+            other_address: true,
+            drop_first: 0,
+            error: None,
+            classic: false,
+        }
+    }
+}
+
+const NAT_IP: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+
+/// RFC 5780 server on 127.0.0.1 and 127.0.0.2, each on two ports.
+struct StunServer {
+    primary: SocketAddr,
+    other: SocketAddr,
+    requests: Arc<AtomicUsize>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for StunServer {
+    fn drop(&mut self) {
+        for t in &self.tasks {
+            t.abort();
+        }
+    }
+}
+
+fn bind_pair(ip1: IpAddr, ip2: IpAddr) -> (std::net::UdpSocket, std::net::UdpSocket) {
+    loop {
+        let a = std::net::UdpSocket::bind((ip1, 0)).unwrap();
+        let port = a.local_addr().unwrap().port();
+        if let Ok(b) = std::net::UdpSocket::bind((ip2, port)) {
+            return (a, b);
+        }
+    }
+}
+
+fn response(
+    request: &Message<'_>,
+    from: SocketAddr,
+    local: SocketAddr,
+    primary_port: u16,
+    other: SocketAddr,
+    opts: ServerOpts,
+) -> Vec<u8> {
+    let mut b = if let Some(code) = opts.error {
+        let mut b = MessageBuilder::new(codec::BINDING_ERROR, request.txid);
+        b.error_code(code, "Test Error");
+        b
+    } else {
+        MessageBuilder::new(codec::BINDING_SUCCESS, request.txid)
+    };
+    let last_octet = match local.ip() {
+        IpAddr::V4(ip) => ip.octets()[3] as u16,
+        IpAddr::V6(ip) => ip.octets()[15] as u16,
+    };
+    let alt_port = (local.port() != primary_port) as u16;
+    let mapped = match opts.nat {
+        FakeNat::None => from,
+        FakeNat::EndpointIndependent => (NAT_IP, from.port() ^ 1).into(),
+        FakeNat::AddressDependent => (NAT_IP, from.port() ^ (last_octet << 1)).into(),
+        FakeNat::AddressAndPortDependent => {
+            (NAT_IP, from.port() ^ (last_octet << 1) ^ (alt_port << 4)).into()
+        }
+    };
+    if opts.error.is_none() && opts.classic {
+        b.addr(codec::ATTR_MAPPED_ADDRESS, mapped);
+    } else if opts.error.is_none() {
+        b.xor_addr(codec::ATTR_XOR_MAPPED_ADDRESS, mapped);
+        if opts.other_address {
+            b.addr(codec::ATTR_OTHER_ADDRESS, other);
+        }
+    }
+    b.finish()
+}
+
+impl StunServer {
+    fn start(opts: ServerOpts) -> Self {
+        let (ip1, ip2) = (
+            IpAddr::from(Ipv4Addr::LOCALHOST),
+            IpAddr::from(Ipv4Addr::new(127, 0, 0, 2)),
+        );
+        let (a1, b1) = bind_pair(ip1, ip2);
+        let (a2, b2) = bind_pair(ip1, ip2);
+        let primary = a1.local_addr().unwrap();
+        let other = b2.local_addr().unwrap();
+        Self::start_sockets(vec![a1, a2, b1, b2], primary, other, opts)
+    }
+
+    fn start_v6(opts: ServerOpts) -> Self {
+        let sock = std::net::UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let primary = sock.local_addr().unwrap();
+        Self::start_sockets(vec![sock], primary, primary, opts)
+    }
+
+    fn start_sockets(
+        socks: Vec<std::net::UdpSocket>,
+        primary: SocketAddr,
+        other: SocketAddr,
+        opts: ServerOpts,
+    ) -> Self {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let tasks = socks
+            .into_iter()
+            .map(|sock| {
+                sock.set_nonblocking(true).unwrap();
+                let sock = tokio::net::UdpSocket::from_std(sock).unwrap();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let local = sock.local_addr().unwrap();
+                    let mut buf = [0u8; 1500];
+                    loop {
+                        let (sz, from) = sock.recv_from(&mut buf).await.unwrap();
+                        let Some(request) = Message::parse(&buf[..sz]) else {
+                            continue;
+                        };
+                        if requests.fetch_add(1, Ordering::SeqCst) < opts.drop_first {
+                            continue;
+                        }
+                        let resp = response(&request, from, local, primary.port(), other, opts);
+                        sock.send_to(&resp, from).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        Self {
+            primary,
+            other,
+            requests,
+            tasks,
+        }
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+/// A socket whose receive loop feeds the STUN client and forwards everything else.
+struct Peer {
+    sock: Arc<UdpSocket>,
+    client: Arc<StunClient>,
+    other_rx: mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Peer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn peer(bind: SocketAddr, opts: StunOpts) -> Peer {
+    let sock = Arc::new(UdpSocket::bind_udp(bind, BindOpts::default()).unwrap());
+    let client = Arc::new(StunClient::new(opts));
+    let (tx, other_rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn({
+        let sock = sock.clone();
+        let client = client.clone();
+        async move {
+            let mut buf = [0u8; 1500];
+            loop {
+                let (sz, from) = sock.recv_from(&mut buf).await.unwrap();
+                if !client.handle_datagram(&buf[..sz], from) {
+                    tx.send((buf[..sz].to_vec(), from)).unwrap();
+                }
+            }
+        }
+    });
+    Peer {
+        sock,
+        client,
+        other_rx,
+        task,
+    }
+}
+
+fn fast_opts() -> StunOpts {
+    StunOpts {
+        rto: Duration::from_millis(20),
+        max_requests: 4,
+    }
+}
+
+#[tokio::test]
+async fn test_binding_dualstack() {
+    let server = StunServer::start(Default::default());
+    let p = peer((Ipv6Addr::UNSPECIFIED, 0).into(), fast_opts());
+    assert!(p.sock.is_dualstack());
+    let binding = timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(binding.server, server.primary);
+    assert_eq!(
+        binding.mapped_addr,
+        (Ipv4Addr::LOCALHOST, p.sock.bind_addr().port()).into()
+    );
+    assert_eq!(binding.other_addr, Some(server.other));
+}
+
+#[tokio::test]
+async fn test_binding_ipv6() {
+    let server = StunServer::start_v6(ServerOpts {
+        other_address: false,
+        ..Default::default()
+    });
+    let p = peer((Ipv6Addr::UNSPECIFIED, 0).into(), fast_opts());
+    let binding = timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        binding.mapped_addr,
+        (Ipv6Addr::LOCALHOST, p.sock.bind_addr().port()).into()
+    );
+    assert_eq!(binding.other_addr, None);
+}
+
+#[tokio::test]
+async fn test_binding_classic_server() {
+    let server = StunServer::start_v6(ServerOpts {
+        classic: true,
+        other_address: false,
+        ..Default::default()
+    });
+    let p = peer((Ipv6Addr::UNSPECIFIED, 0).into(), fast_opts());
+    let binding = timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        binding.mapped_addr,
+        (Ipv6Addr::LOCALHOST, p.sock.bind_addr().port()).into()
+    );
+}
+
+#[tokio::test]
+async fn test_huge_rto_does_not_overflow() {
+    let server = StunServer::start_v6(Default::default());
+    let p = peer(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        StunOpts {
+            // The only request waits for 16 * rto.
+            rto: Duration::MAX / 4,
+            max_requests: 1,
+        },
+    );
+    timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_other_traffic_passes_through() {
+    let server = StunServer::start(Default::default());
+    let mut p = peer((Ipv6Addr::UNSPECIFIED, 0).into(), fast_opts());
+    let port = p.sock.bind_addr().port();
+    let other = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let to = (Ipv4Addr::LOCALHOST, port);
+
+    other.send_to(b"d1:y1:qe", to).unwrap();
+    // A STUN response, but not to our request.
+    let unrelated =
+        MessageBuilder::new(codec::BINDING_SUCCESS, codec::new_transaction_id()).finish();
+    other.send_to(&unrelated, to).unwrap();
+    timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (data, from) = timeout(TIMEOUT, p.other_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(data, b"d1:y1:qe");
+    assert_eq!(from, other.local_addr().unwrap());
+    let (data, _) = timeout(TIMEOUT, p.other_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(data, unrelated);
+    assert!(p.other_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_retransmit() {
+    let server = StunServer::start(ServerOpts {
+        drop_first: 2,
+        ..Default::default()
+    });
+    let p = peer((Ipv4Addr::LOCALHOST, 0).into(), fast_opts());
+    timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(server.requests(), 3);
+}
+
+#[tokio::test]
+async fn test_timeout() {
+    let server = StunServer::start(ServerOpts {
+        drop_first: usize::MAX,
+        ..Default::default()
+    });
+    let p = peer((Ipv4Addr::LOCALHOST, 0).into(), fast_opts());
+    let start = Instant::now();
+    let res = timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap();
+    assert!(
+        matches!(res, Err(Error::StunTimeout { server: s }) if s == server.primary),
+        "{res:?}"
+    );
+    assert_eq!(server.requests(), 4);
+    // 20 + 40 + 80 ms, then 16 * 20ms for the last one.
+    assert!(start.elapsed() >= Duration::from_millis(460));
+}
+
+#[tokio::test]
+async fn test_error_response() {
+    let server = StunServer::start(ServerOpts {
+        error: Some(420),
+        ..Default::default()
+    });
+    let p = peer((Ipv4Addr::LOCALHOST, 0).into(), fast_opts());
+    let res = timeout(TIMEOUT, p.client.binding(&p.sock, server.primary))
+        .await
+        .unwrap();
+    assert!(
+        matches!(res, Err(Error::StunErrorResponse { code: 420, .. })),
+        "{res:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_external_addr_multiple_servers() {
+    let dead = StunServer::start(ServerOpts {
+        drop_first: usize::MAX,
+        ..Default::default()
+    });
+    let live = StunServer::start(ServerOpts {
+        nat: FakeNat::EndpointIndependent,
+        ..Default::default()
+    });
+    let p = peer((Ipv6Addr::UNSPECIFIED, 0).into(), fast_opts());
+    let start = Instant::now();
+    let binding = timeout(
+        TIMEOUT,
+        p.client
+            .external_addr(&p.sock, &[dead.primary, live.primary]),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(binding.server, live.primary);
+    assert_eq!(binding.mapped_addr.ip(), IpAddr::from(NAT_IP));
+    // Didn't wait for the dead server to time out.
+    assert!(start.elapsed() < Duration::from_millis(400));
+
+    let res = p.client.external_addr(&p.sock, &[dead.primary]).await;
+    assert!(matches!(res, Err(Error::StunTimeout { .. })), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_nat_mapping() {
+    for (nat, expected) in [
+        (FakeNat::None, NatMapping::NoNat),
+        (
+            FakeNat::EndpointIndependent,
+            NatMapping::EndpointIndependent,
+        ),
+        (FakeNat::AddressDependent, NatMapping::AddressDependent),
+        (
+            FakeNat::AddressAndPortDependent,
+            NatMapping::AddressAndPortDependent,
+        ),
+    ] {
+        let server = StunServer::start(ServerOpts {
+            nat,
+            ..Default::default()
+        });
+        for bind in [
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        ] {
+            let p = peer(bind, fast_opts());
+            let mapping = timeout(TIMEOUT, p.client.nat_mapping(&p.sock, server.primary))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(mapping, expected, "{nat:?} bind={bind:?}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_nat_mapping_needs_other_address() {
+    let server = StunServer::start(ServerOpts {
+        nat: FakeNat::EndpointIndependent,
+        other_address: false,
+        ..Default::default()
+    });
+    let p = peer((Ipv4Addr::LOCALHOST, 0).into(), fast_opts());
+    let res = timeout(TIMEOUT, p.client.nat_mapping(&p.sock, server.primary))
+        .await
+        .unwrap();
+    assert!(matches!(res, Err(Error::StunNoOtherAddress)), "{res:?}");
+}
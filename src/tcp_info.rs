@@ -0,0 +1,241 @@
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+
+use crate::TcpStreamExt;
+
+/// Connection state from TCP_INFO. Fields added to the kernel after 3.x are None if the running
+/// kernel doesn't report them. Times are rounded to microseconds, rates are in bytes per second.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpInfo {
+    /// TCP_ESTABLISHED etc.
+    pub state: u8,
+    /// Congestion avoidance state (TCP_CA_Open etc).
+    pub ca_state: u8,
+    /// Consecutive retransmits of the current unacked segment.
+    pub retransmits: u8,
+    /// The TCP Fast Open data we sent in the SYN was acked.
+    pub syn_data_acked: bool,
+    pub rto: Duration,
+    pub snd_mss: u32,
+    pub rcv_mss: u32,
+    /// Segments sent but not acked yet.
+    pub unacked: u32,
+    pub lost: u32,
+    /// Retransmitted segments not acked yet.
+    pub retrans: u32,
+    pub pmtu: u32,
+    /// Smoothed RTT.
+    pub rtt: Duration,
+    pub rtt_var: Duration,
+    pub snd_ssthresh: u32,
+    /// Congestion window, in segments.
+    pub snd_cwnd: u32,
+    pub reordering: u32,
+    pub total_retrans: u32,
+    pub pacing_rate: Option<u64>,
+    /// None if unlimited or not reported.
+    pub max_pacing_rate: Option<u64>,
+    pub bytes_acked: Option<u64>,
+    pub bytes_received: Option<u64>,
+    pub segs_out: Option<u32>,
+    pub segs_in: Option<u32>,
+    /// Bytes written by the application but not sent yet.
+    pub notsent_bytes: Option<u32>,
+    pub min_rtt: Option<Duration>,
+    pub delivery_rate: Option<u64>,
+    /// The delivery rate was limited by the application not sending enough.
+    pub delivery_rate_app_limited: Option<bool>,
+    /// Time spent sending data.
+    pub busy_time: Option<Duration>,
+    /// Time spent limited by the receive window.
+    pub rwnd_limited: Option<Duration>,
+    /// Time spent limited by the send buffer.
+    pub sndbuf_limited: Option<Duration>,
+    /// Segments delivered, including retransmits.
+    pub delivered: Option<u32>,
+    pub bytes_sent: Option<u64>,
+    pub bytes_retrans: Option<u64>,
+    pub snd_wnd: Option<u32>,
+    pub rcv_wnd: Option<u32>,
+}
+
+// Offsets into struct tcp_info (linux/tcp.h).
+const STATE: usize = 0;
+const CA_STATE: usize = 1;
+const RETRANSMITS: usize = 2;
+const OPTIONS: usize = 5;
+const DELIVERY_RATE_APP_LIMITED: usize = 7;
+const RTO: usize = 8;
+const SND_MSS: usize = 16;
+const RCV_MSS: usize = 20;
+const UNACKED: usize = 24;
+const LOST: usize = 32;
+const RETRANS: usize = 36;
+const PMTU: usize = 60;
+const RTT: usize = 68;
+const RTTVAR: usize = 72;
+const SND_SSTHRESH: usize = 76;
+const SND_CWND: usize = 80;
+const REORDERING: usize = 88;
+const TOTAL_RETRANS: usize = 100;
+const PACING_RATE: usize = 104;
+const MAX_PACING_RATE: usize = 112;
+const BYTES_ACKED: usize = 120;
+const BYTES_RECEIVED: usize = 128;
+const SEGS_OUT: usize = 136;
+const SEGS_IN: usize = 140;
+const NOTSENT_BYTES: usize = 144;
+const MIN_RTT: usize = 148;
+const DELIVERY_RATE: usize = 160;
+const BUSY_TIME: usize = 168;
+const RWND_LIMITED: usize = 176;
+const SNDBUF_LIMITED: usize = 184;
+const DELIVERED: usize = 192;
+const BYTES_SENT: usize = 200;
+const BYTES_RETRANS: usize = 208;
+const SND_WND: usize = 228;
+const RCV_WND: usize = 232;
+const TCP_INFO_LEN: usize = 236;
+
+const TCPI_OPT_SYN_DATA: u8 = 32;
+
+impl TcpInfo {
+    /// Parse the first `len` bytes of `buf` as returned by getsockopt(TCP_INFO).
+    fn parse(buf: &[u8]) -> Self {
+        let u8_at = |off: usize| buf.get(off).copied();
+        let u32_at = |off: usize| {
+            buf.get(off..off + 4)
+                .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+        };
+        let u64_at = |off: usize| {
+            buf.get(off..off + 8)
+                .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+        };
+        let usecs_at = |off: usize| u32_at(off).map(|us| Duration::from_micros(us as u64));
+        let delivery_rate = u64_at(DELIVERY_RATE);
+        TcpInfo {
+            state: u8_at(STATE).unwrap_or_default(),
+            ca_state: u8_at(CA_STATE).unwrap_or_default(),
+            retransmits: u8_at(RETRANSMITS).unwrap_or_default(),
+            syn_data_acked: u8_at(OPTIONS).unwrap_or_default() & TCPI_OPT_SYN_DATA != 0,
+            rto: usecs_at(RTO).unwrap_or_default(),
+            snd_mss: u32_at(SND_MSS).unwrap_or_default(),
+            rcv_mss: u32_at(RCV_MSS).unwrap_or_default(),
+            unacked: u32_at(UNACKED).unwrap_or_default(),
+            lost: u32_at(LOST).unwrap_or_default(),
+            retrans: u32_at(RETRANS).unwrap_or_default(),
+            pmtu: u32_at(PMTU).unwrap_or_default(),
+            rtt: usecs_at(RTT).unwrap_or_default(),
+            rtt_var: usecs_at(RTTVAR).unwrap_or_default(),
+            snd_ssthresh: u32_at(SND_SSTHRESH).unwrap_or_default(),
+            snd_cwnd: u32_at(SND_CWND).unwrap_or_default(),
+            reordering: u32_at(REORDERING).unwrap_or_default(),
+            total_retrans: u32_at(TOTAL_RETRANS).unwrap_or_default(),
+            pacing_rate: u64_at(PACING_RATE),
+            max_pacing_rate: u64_at(MAX_PACING_RATE).filter(|r| *r != u64::MAX),
+            bytes_acked: u64_at(BYTES_ACKED),
+            bytes_received: u64_at(BYTES_RECEIVED),
+            segs_out: u32_at(SEGS_OUT),
+            segs_in: u32_at(SEGS_IN),
+            notsent_bytes: u32_at(NOTSENT_BYTES),
+            min_rtt: usecs_at(MIN_RTT),
+            delivery_rate,
+            // A bit field added together with delivery_rate.
+            delivery_rate_app_limited: delivery_rate
+                .and(u8_at(DELIVERY_RATE_APP_LIMITED))
+                .map(|b| b & 1 != 0),
+            busy_time: u64_at(BUSY_TIME).map(Duration::from_micros),
+            rwnd_limited: u64_at(RWND_LIMITED).map(Duration::from_micros),
+            sndbuf_limited: u64_at(SNDBUF_LIMITED).map(Duration::from_micros),
+            delivered: u32_at(DELIVERED),
+            bytes_sent: u64_at(BYTES_SENT),
+            bytes_retrans: u64_at(BYTES_RETRANS),
+            snd_wnd: u32_at(SND_WND),
+            rcv_wnd: u32_at(RCV_WND),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn get(sock: &impl std::os::fd::AsFd) -> std::io::Result<TcpInfo> {
+    let mut buf = [0u8; TCP_INFO_LEN];
+    let len = crate::sockopt::get_buf(sock, libc::IPPROTO_TCP, libc::TCP_INFO, &mut buf)?;
+    Ok(TcpInfo::parse(&buf[..len.min(TCP_INFO_LEN)]))
+}
+
+/// Change of the [`TcpInfo`] counters since the previous sample. Counters the kernel doesn't
+/// report are None.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpInfoDelta {
+    pub interval: Duration,
+    pub bytes_acked: Option<u64>,
+    pub bytes_received: Option<u64>,
+    pub bytes_sent: Option<u64>,
+    pub bytes_retrans: Option<u64>,
+    pub segs_out: Option<u32>,
+    pub segs_in: Option<u32>,
+    pub total_retrans: u32,
+    /// The latest sample, for gauges like RTT and cwnd.
+    pub info: TcpInfo,
+}
+
+impl TcpInfoDelta {
+    /// Acked bytes per second over the interval.
+    pub fn ack_rate(&self) -> Option<u64> {
+        self.bytes_acked.map(|b| per_second(b, self.interval))
+    }
+
+    /// Received bytes per second over the interval.
+    pub fn receive_rate(&self) -> Option<u64> {
+        self.bytes_received.map(|b| per_second(b, self.interval))
+    }
+}
+
+fn per_second(bytes: u64, interval: Duration) -> u64 {
+    if interval.is_zero() {
+        return 0;
+    }
+    (bytes as f64 / interval.as_secs_f64()) as u64
+}
+
+/// Reports per-interval changes of a stream's [`TcpInfo`] counters.
+///
+/// Call [`tick`](Self::tick) periodically.
+#[derive(Debug, Default)]
+pub struct TcpInfoSampler {
+    last: Option<(Instant, TcpInfo)>,
+}
+
+impl TcpInfoSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns None on the first call, as there's nothing to compare to yet.
+    pub fn tick(&mut self, stream: &TcpStream) -> std::io::Result<Option<TcpInfoDelta>> {
+        let info = stream.tcp_info()?;
+        Ok(self.sample(Instant::now(), info))
+    }
+
+    fn sample(&mut self, now: Instant, info: TcpInfo) -> Option<TcpInfoDelta> {
+        let (prev_at, prev) = self.last.replace((now, info))?;
+        fn sub<T: Copy>(cur: Option<T>, prev: Option<T>, f: fn(T, T) -> T) -> Option<T> {
+            Some(f(cur?, prev?))
+        }
+        Some(TcpInfoDelta {
+            interval: now.saturating_duration_since(prev_at),
+            bytes_acked: sub(info.bytes_acked, prev.bytes_acked, u64::wrapping_sub),
+            bytes_received: sub(info.bytes_received, prev.bytes_received, u64::wrapping_sub),
+            bytes_sent: sub(info.bytes_sent, prev.bytes_sent, u64::wrapping_sub),
+            bytes_retrans: sub(info.bytes_retrans, prev.bytes_retrans, u64::wrapping_sub),
+            segs_out: sub(info.segs_out, prev.segs_out, u32::wrapping_sub),
+            segs_in: sub(info.segs_in, prev.segs_in, u32::wrapping_sub),
+            total_retrans: info.total_retrans.wrapping_sub(prev.total_retrans),
+            info,
+        })
+    }
+}
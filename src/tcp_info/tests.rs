@@ -0,0 +1,157 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use super::TcpInfo;
+use crate::{BindOpts, TcpInfoSampler, TcpListener, TcpStreamExt};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+// linux/tcp_states.h
+const TCP_ESTABLISHED: u8 = 1;
+
+async fn connected_pair(listen: SocketAddr) -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind_tcp(listen, BindOpts::default()).unwrap();
+    let port = listener.bind_addr().port();
+    let (client, accepted) = tokio::join!(
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)),
+        listener.accept()
+    );
+    (client.unwrap(), accepted.unwrap().0)
+}
+
+// Whether the running kernel's struct tcp_info includes the `size`-byte field at `offset`.
+fn reports(stream: &TcpStream, offset: usize, size: usize) -> bool {
+    let mut buf = [0u8; super::TCP_INFO_LEN];
+    let len = crate::sockopt::get_buf(stream, libc::IPPROTO_TCP, libc::TCP_INFO, &mut buf).unwrap();
+    len >= offset + size
+}
+
+async fn transfer(from: &mut TcpStream, to: &mut TcpStream, len: usize) {
+    let data = vec![7u8; len];
+    let mut buf = vec![0u8; len];
+    let (w, r) = tokio::join!(from.write_all(&data), to.read_exact(&mut buf));
+    w.unwrap();
+    r.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_info() {
+    let (mut client, mut accepted) = connected_pair((Ipv6Addr::UNSPECIFIED, 0).into()).await;
+    timeout(TIMEOUT, transfer(&mut accepted, &mut client, 1 << 20))
+        .await
+        .unwrap();
+
+    let info = accepted.tcp_info().unwrap();
+    assert_eq!(info.state, TCP_ESTABLISHED);
+    assert!(info.snd_mss > 0);
+    assert!(info.snd_cwnd > 0);
+    assert!(info.rtt > Duration::ZERO);
+    assert_eq!(info.max_pacing_rate, None);
+    // Newer fields are only checked if the kernel has them: bytes_acked since 4.1, min_rtt since
+    // 4.6, delivery_rate since 4.9, bytes_sent since 4.19 and rcv_wnd since 6.2.
+    if reports(&accepted, super::BYTES_ACKED, 8) {
+        assert!(info.bytes_acked.unwrap() >= 1 << 20);
+    }
+    if reports(&accepted, super::MIN_RTT, 4) {
+        assert!(info.min_rtt.is_some());
+    }
+    if reports(&accepted, super::DELIVERY_RATE, 8) {
+        assert!(info.delivery_rate.is_some());
+    }
+    if reports(&accepted, super::BYTES_SENT, 8) {
+        assert!(info.bytes_sent.unwrap() >= 1 << 20);
+    }
+    if reports(&accepted, super::RCV_WND, 4) {
+        assert!(info.rcv_wnd.is_some());
+    }
+
+    if reports(&client, super::BYTES_RECEIVED, 8) {
+        let info = client.tcp_info().unwrap();
+        assert!(info.bytes_received.unwrap() >= 1 << 20);
+    }
+
+    accepted.set_max_pacing_rate(Some(1_000_000)).unwrap();
+    assert_eq!(
+        accepted.tcp_info().unwrap().max_pacing_rate,
+        Some(1_000_000)
+    );
+}
+
+#[tokio::test]
+async fn test_tcp_info_older_kernel() {
+    let (client, _accepted) = connected_pair((Ipv4Addr::LOCALHOST, 0).into()).await;
+    let mut buf = [0u8; super::TCP_INFO_LEN];
+    let len =
+        crate::sockopt::get_buf(&client, libc::IPPROTO_TCP, libc::TCP_INFO, &mut buf).unwrap();
+    let full = TcpInfo::parse(&buf[..len]);
+
+    // The 3.x struct.
+    let old = TcpInfo::parse(&buf[..104]);
+    assert_eq!(old.snd_cwnd, full.snd_cwnd);
+    assert_eq!(old.total_retrans, full.total_retrans);
+    assert_eq!(old.pacing_rate, None);
+    assert_eq!(old.bytes_acked, None);
+    assert_eq!(old.min_rtt, None);
+
+    // 4.6: min_rtt, but no delivery rate.
+    let old = TcpInfo::parse(&buf[..160]);
+    assert_eq!(old.min_rtt, full.min_rtt);
+    assert_eq!(old.delivery_rate, None);
+    assert_eq!(old.delivery_rate_app_limited, None);
+    assert_eq!(old.bytes_sent, None);
+}
+
+#[test]
+fn test_parse_u64_limited_times() {
+    // More than u32::MAX microseconds (~71 minutes).
+    let busy = (1u64 << 33) + 5;
+    let mut buf = [0u8; super::TCP_INFO_LEN];
+    buf[super::BUSY_TIME..super::BUSY_TIME + 8].copy_from_slice(&busy.to_ne_bytes());
+    buf[super::RWND_LIMITED..super::RWND_LIMITED + 8].copy_from_slice(&7u64.to_ne_bytes());
+    let info = TcpInfo::parse(&buf);
+    assert_eq!(info.busy_time, Some(Duration::from_micros(busy)));
+    assert_eq!(info.rwnd_limited, Some(Duration::from_micros(7)));
+    assert_eq!(info.sndbuf_limited, Some(Duration::ZERO));
+}
+
+#[tokio::test]
+async fn test_sampler() {
+    let (mut client, mut accepted) = connected_pair((Ipv6Addr::UNSPECIFIED, 0).into()).await;
+    let mut sender = TcpInfoSampler::new();
+    let mut receiver = TcpInfoSampler::new();
+    assert!(sender.tick(&accepted).unwrap().is_none());
+    assert!(receiver.tick(&client).unwrap().is_none());
+
+    timeout(TIMEOUT, transfer(&mut accepted, &mut client, 1 << 20))
+        .await
+        .unwrap();
+    // The counters are all there since 4.2, except bytes_sent (4.19).
+    let has_counters = reports(&accepted, super::SEGS_IN, 4);
+    let has_bytes_sent = reports(&accepted, super::BYTES_SENT, 8);
+    let delta = sender.tick(&accepted).unwrap().unwrap();
+    assert_eq!(delta.info.state, TCP_ESTABLISHED);
+    if has_counters {
+        assert!(delta.bytes_acked.unwrap() >= 1 << 20);
+        assert!(delta.segs_out.unwrap() > 0);
+        assert!(delta.ack_rate().unwrap() > 0);
+        let delta = receiver.tick(&client).unwrap().unwrap();
+        assert!(delta.bytes_received.unwrap() >= 1 << 20);
+    }
+    if has_bytes_sent {
+        assert!(delta.bytes_sent.unwrap() >= 1 << 20);
+    }
+
+    // Nothing happened since.
+    let delta = sender.tick(&accepted).unwrap().unwrap();
+    if has_counters {
+        assert_eq!(delta.bytes_acked, Some(0));
+    }
+    if has_bytes_sent {
+        assert_eq!(delta.bytes_sent, Some(0));
+    }
+}
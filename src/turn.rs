@@ -0,0 +1,564 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{debug, trace};
+
+use crate::{
+    Error, StunClient, StunOpts, UdpSocket,
+    addr::TryToV4,
+    stun::codec::{self, Message, MessageBuilder},
+};
+
+const ALLOCATE: u16 = 0x0003;
+const REFRESH: u16 = 0x0004;
+const SEND_INDICATION: u16 = 0x0016;
+const DATA_INDICATION: u16 = 0x0017;
+const CREATE_PERMISSION: u16 = 0x0008;
+const CHANNEL_BIND: u16 = 0x0009;
+
+const ATTR_CHANNEL_NUMBER: u16 = 0x000c;
+const ATTR_LIFETIME: u16 = 0x000d;
+const ATTR_XOR_PEER_ADDRESS: u16 = 0x0012;
+const ATTR_DATA: u16 = 0x0013;
+const ATTR_XOR_RELAYED_ADDRESS: u16 = 0x0016;
+const ATTR_REQUESTED_TRANSPORT: u16 = 0x0019;
+
+const PROTO_UDP: u8 = 17;
+const CHANNELS: std::ops::RangeInclusive<u16> = 0x4000..=0x4fff;
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(600);
+const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+const CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+// Refresh this long before expiry (or at half the lifetime if it's shorter).
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+// Relayed datagrams not yet read with recv_from(). Further ones are dropped.
+const RECV_QUEUE_LEN: usize = 256;
+
+#[derive(Clone)]
+pub struct TurnOpts {
+    pub username: String,
+    pub password: String,
+    /// Requested allocation lifetime. The server may grant a different one.
+    pub lifetime: Duration,
+    pub stun: StunOpts,
+}
+
+impl Default for TurnOpts {
+    fn default() -> Self {
+        Self {
+            username: Default::default(),
+            password: Default::default(),
+            lifetime: DEFAULT_LIFETIME,
+            stun: Default::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for TurnOpts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TurnOpts")
+            .field("username", &self.username)
+            .field("lifetime", &self.lifetime)
+            .field("stun", &self.stun)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Auth {
+    realm: String,
+    nonce: String,
+    key: [u8; 16],
+}
+
+struct Allocation {
+    relayed: SocketAddr,
+    mapped: Option<SocketAddr>,
+    lifetime: Duration,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    auth: Option<Auth>,
+    allocation: Option<Allocation>,
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<SocketAddr, (u16, Instant)>,
+    channel_peers: HashMap<u16, SocketAddr>,
+}
+
+// A channel number picked by channel_bind(). Released unless the bind succeeds, including when
+// the bind is cancelled.
+struct ChannelReservation<'a> {
+    state: &'a Mutex<State>,
+    channel: u16,
+    peer: SocketAddr,
+}
+
+impl Drop for ChannelReservation<'_> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if state.channel_peers.get(&self.channel) == Some(&self.peer) {
+            state.channel_peers.remove(&self.channel);
+        }
+    }
+}
+
+// What maintain() needs to refresh now, and when to check next.
+struct Due {
+    allocation: bool,
+    permissions: Vec<IpAddr>,
+    channels: Vec<SocketAddr>,
+    next: Instant,
+}
+
+impl Due {
+    fn is_empty(&self) -> bool {
+        !self.allocation && self.permissions.is_empty() && self.channels.is_empty()
+    }
+}
+
+fn refresh_at(expires: Instant, lifetime: Duration) -> Instant {
+    expires - REFRESH_MARGIN.min(lifetime / 2)
+}
+
+/// TURN client (RFC 8656) relaying UDP through a server, for peers that can't be reached
+/// directly.
+///
+/// Like [`StunClient`], it shares the socket: pass every received datagram to
+/// [`handle_datagram`](Self::handle_datagram). Once [`allocate`](Self::allocate)d, use
+/// [`send_to`](Self::send_to) and [`recv_from`](Self::recv_from) as with a UDP socket, and
+/// run [`maintain`](Self::maintain) to keep the allocation, permissions and channels alive.
+pub struct TurnClient {
+    sock: Arc<UdpSocket>,
+    server: SocketAddr,
+    opts: TurnOpts,
+    stun: StunClient,
+    state: Mutex<State>,
+    data_tx: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+    data_rx: tokio::sync::Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+}
+
+impl TurnClient {
+    pub fn new(sock: Arc<UdpSocket>, server: SocketAddr, opts: TurnOpts) -> Self {
+        let (data_tx, data_rx) = mpsc::channel(RECV_QUEUE_LEN);
+        Self {
+            sock,
+            server: server.try_to_ipv4(),
+            stun: StunClient::new(opts.stun),
+            opts,
+            state: Default::default(),
+            data_tx,
+            data_rx: tokio::sync::Mutex::new(data_rx),
+        }
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// The address peers send to. None until allocated.
+    pub fn relayed_addr(&self) -> Option<SocketAddr> {
+        self.state
+            .lock()
+            .unwrap()
+            .allocation
+            .as_ref()
+            .map(|a| a.relayed)
+    }
+
+    /// Our address as seen by the server.
+    pub fn mapped_addr(&self) -> Option<SocketAddr> {
+        self.state
+            .lock()
+            .unwrap()
+            .allocation
+            .as_ref()
+            .and_then(|a| a.mapped)
+    }
+
+    /// Feed a datagram received on the socket. Returns true if it came from the TURN server, and
+    /// so isn't meant for anyone else.
+    pub fn handle_datagram(&self, buf: &[u8], from: SocketAddr) -> bool {
+        if from.try_to_ipv4() != self.server {
+            return false;
+        }
+        if buf.first().is_some_and(|b| (0x40..=0x4f).contains(b)) {
+            self.handle_channel_data(buf);
+            return true;
+        }
+        if let Some(msg) = Message::parse(buf)
+            && msg.msg_type == DATA_INDICATION
+        {
+            match (msg.xor_addr(ATTR_XOR_PEER_ADDRESS), msg.attr(ATTR_DATA)) {
+                (Some(peer), Some(data)) => self.deliver(data, peer),
+                _ => debug!("invalid TURN data indication"),
+            }
+            return true;
+        }
+        self.stun.handle_datagram(buf, from)
+    }
+
+    fn handle_channel_data(&self, buf: &[u8]) {
+        let Some(header) = buf.get(..4) else {
+            return;
+        };
+        let channel = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let Some(data) = buf.get(4..4 + len) else {
+            debug!(channel, len, "truncated TURN ChannelData");
+            return;
+        };
+        let peer = self
+            .state
+            .lock()
+            .unwrap()
+            .channel_peers
+            .get(&channel)
+            .copied();
+        match peer {
+            Some(peer) => self.deliver(data, peer),
+            None => debug!(channel, "TURN ChannelData on an unknown channel"),
+        }
+    }
+
+    fn deliver(&self, data: &[u8], peer: SocketAddr) {
+        if self.data_tx.try_send((data.to_vec(), peer)).is_err() {
+            trace!(?peer, "TURN receive queue full, dropping datagram");
+        }
+    }
+
+    /// Create the allocation and return the relayed address.
+    pub async fn allocate(&self) -> crate::Result<SocketAddr> {
+        let lifetime = self.opts.lifetime.as_secs() as u32;
+        let response = self
+            .request(ALLOCATE, |b| {
+                b.attr(ATTR_REQUESTED_TRANSPORT, &[PROTO_UDP, 0, 0, 0])
+                    .attr(ATTR_LIFETIME, &lifetime.to_be_bytes());
+            })
+            .await?;
+        let msg = Message::parse(&response).ok_or(Error::StunInvalidResponse)?;
+        let relayed = msg
+            .xor_addr(ATTR_XOR_RELAYED_ADDRESS)
+            .ok_or(Error::StunInvalidResponse)?;
+        let lifetime = msg
+            .u32(ATTR_LIFETIME)
+            .map(|s| Duration::from_secs(s as u64))
+            .unwrap_or(DEFAULT_LIFETIME);
+        let mapped = msg.xor_addr(codec::ATTR_XOR_MAPPED_ADDRESS);
+        debug!(server=?self.server, ?relayed, ?mapped, ?lifetime, "TURN allocation created");
+        self.state.lock().unwrap().allocation = Some(Allocation {
+            relayed,
+            mapped,
+            lifetime,
+            expires: Instant::now() + lifetime,
+        });
+        Ok(relayed)
+    }
+
+    /// Refresh the allocation now. Returns the lifetime granted by the server.
+    pub async fn refresh(&self) -> crate::Result<Duration> {
+        self.check_allocated()?;
+        let lifetime = self.opts.lifetime.as_secs() as u32;
+        let response = self
+            .request(REFRESH, |b| {
+                b.attr(ATTR_LIFETIME, &lifetime.to_be_bytes());
+            })
+            .await?;
+        let msg = Message::parse(&response).ok_or(Error::StunInvalidResponse)?;
+        let lifetime = msg
+            .u32(ATTR_LIFETIME)
+            .map(|s| Duration::from_secs(s as u64))
+            .unwrap_or(DEFAULT_LIFETIME);
+        trace!(server=?self.server, ?lifetime, "TURN allocation refreshed");
+        if let Some(a) = self.state.lock().unwrap().allocation.as_mut() {
+            a.lifetime = lifetime;
+            a.expires = Instant::now() + lifetime;
+        }
+        Ok(lifetime)
+    }
+
+    /// Delete the allocation on the server.
+    pub async fn close(&self) -> crate::Result<()> {
+        self.check_allocated()?;
+        self.request(REFRESH, |b| {
+            b.attr(ATTR_LIFETIME, &0u32.to_be_bytes());
+        })
+        .await?;
+        let mut state = self.state.lock().unwrap();
+        state.allocation = None;
+        state.permissions.clear();
+        state.channels.clear();
+        state.channel_peers.clear();
+        Ok(())
+    }
+
+    /// Allow `peers` to send to the relayed address. [`send_to`](Self::send_to) does it
+    /// automatically.
+    pub async fn create_permission(&self, peers: &[IpAddr]) -> crate::Result<()> {
+        self.check_allocated()?;
+        let peers: Vec<IpAddr> = peers.iter().map(|ip| ip.to_canonical()).collect();
+        self.request(CREATE_PERMISSION, |b| {
+            for ip in &peers {
+                b.xor_addr(ATTR_XOR_PEER_ADDRESS, SocketAddr::new(*ip, 0));
+            }
+        })
+        .await?;
+        let expires = Instant::now() + PERMISSION_LIFETIME;
+        let mut state = self.state.lock().unwrap();
+        for ip in peers {
+            state.permissions.insert(ip, expires);
+        }
+        Ok(())
+    }
+
+    /// Bind a channel to `peer`, so that datagrams to and from it have 4 bytes of overhead instead
+    /// of 36. Also creates a permission.
+    pub async fn channel_bind(&self, peer: SocketAddr) -> crate::Result<u16> {
+        self.check_allocated()?;
+        let peer = peer.try_to_ipv4();
+        let (channel, reservation) = {
+            let mut state = self.state.lock().unwrap();
+            match state.channels.get(&peer) {
+                Some((channel, _)) => (*channel, None),
+                None => {
+                    let channel = CHANNELS
+                        .clone()
+                        .find(|c| !state.channel_peers.contains_key(c))
+                        .ok_or(Error::TurnNoFreeChannel)?;
+                    // Reserve the number, so that concurrent binds pick different ones.
+                    state.channel_peers.insert(channel, peer);
+                    let reservation = ChannelReservation {
+                        state: &self.state,
+                        channel,
+                        peer,
+                    };
+                    (channel, Some(reservation))
+                }
+            }
+        };
+        self.request(CHANNEL_BIND, |b| {
+            b.attr(
+                ATTR_CHANNEL_NUMBER,
+                &[(channel >> 8) as u8, channel as u8, 0, 0],
+            )
+            .xor_addr(ATTR_XOR_PEER_ADDRESS, peer);
+        })
+        .await?;
+        std::mem::forget(reservation);
+        debug!(?peer, channel, "TURN channel bound");
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state
+            .channels
+            .insert(peer, (channel, now + CHANNEL_LIFETIME));
+        state.channel_peers.insert(channel, peer);
+        state
+            .permissions
+            .insert(peer.ip(), now + PERMISSION_LIFETIME);
+        Ok(channel)
+    }
+
+    /// Send a datagram to `target` through the relay, creating a permission first if needed.
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        // Both ChannelData and the DATA attribute have a 16-bit length.
+        let len = u16::try_from(buf.len()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "datagram too large to relay through TURN",
+            )
+        })?;
+        let target = target.try_to_ipv4();
+        let (channel, has_permission) = {
+            let state = self.state.lock().unwrap();
+            let now = Instant::now();
+            (
+                state
+                    .channels
+                    .get(&target)
+                    .filter(|(_, expires)| *expires > now)
+                    .map(|(channel, _)| *channel),
+                state
+                    .permissions
+                    .get(&target.ip())
+                    .is_some_and(|expires| *expires > now),
+            )
+        };
+        let msg = if let Some(channel) = channel {
+            let mut msg = Vec::with_capacity(4 + buf.len());
+            msg.extend_from_slice(&channel.to_be_bytes());
+            msg.extend_from_slice(&len.to_be_bytes());
+            msg.extend_from_slice(buf);
+            msg
+        } else {
+            if !has_permission {
+                self.create_permission(&[target.ip()])
+                    .await
+                    .map_err(std::io::Error::other)?;
+            }
+            let mut b = MessageBuilder::new(SEND_INDICATION, codec::new_transaction_id());
+            b.xor_addr(ATTR_XOR_PEER_ADDRESS, target)
+                .attr(ATTR_DATA, buf);
+            b.finish()
+        };
+        self.sock.send_to(&msg, self.server).await?;
+        Ok(buf.len())
+    }
+
+    /// Receive a datagram relayed from a peer. Datagrams larger than `buf` are truncated.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        let (data, from) = self
+            .data_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, from))
+    }
+
+    /// Keep the allocation, permissions and channels alive. Only returns on error.
+    pub async fn maintain(&self) -> crate::Result<()> {
+        loop {
+            let due = self.due(Instant::now())?;
+            if due.is_empty() {
+                tokio::time::sleep_until(due.next).await;
+                continue;
+            }
+            if due.allocation {
+                self.refresh().await?;
+            }
+            if !due.permissions.is_empty() {
+                self.create_permission(&due.permissions).await?;
+            }
+            for peer in due.channels {
+                self.channel_bind(peer).await?;
+            }
+        }
+    }
+
+    fn due(&self, now: Instant) -> crate::Result<Due> {
+        let state = self.state.lock().unwrap();
+        let allocation = state.allocation.as_ref().ok_or(Error::TurnNotAllocated)?;
+        let allocation_at = refresh_at(allocation.expires, allocation.lifetime);
+        let mut due = Due {
+            allocation: allocation_at <= now,
+            permissions: Vec::new(),
+            channels: Vec::new(),
+            next: allocation_at,
+        };
+        for (ip, expires) in &state.permissions {
+            // Channels refresh their peer's permission.
+            if state.channels.keys().any(|p| p.ip() == *ip) {
+                continue;
+            }
+            let at = refresh_at(*expires, PERMISSION_LIFETIME);
+            if at <= now {
+                due.permissions.push(*ip);
+            }
+            due.next = due.next.min(at);
+        }
+        for (peer, (_, expires)) in &state.channels {
+            let at = refresh_at(*expires, CHANNEL_LIFETIME);
+            if at <= now {
+                due.channels.push(*peer);
+            }
+            due.next = due.next.min(at);
+        }
+        Ok(due)
+    }
+
+    fn check_allocated(&self) -> crate::Result<()> {
+        match self.state.lock().unwrap().allocation {
+            Some(_) => Ok(()),
+            None => Err(Error::TurnNotAllocated),
+        }
+    }
+
+    // Send an authenticated request, retrying on 401 Unauthorized (to learn the realm and nonce)
+    // and 438 Stale Nonce. A 401 to a request that had credentials is retried once with the realm
+    // and nonce from the response, in case the server rotated them (RFC 8489 section 9.2.5).
+    async fn request(
+        &self,
+        method: u16,
+        build: impl Fn(&mut MessageBuilder),
+    ) -> crate::Result<Vec<u8>> {
+        let mut last_error = None;
+        let mut retried_unauthorized = false;
+        for _ in 0..3 {
+            let txid = codec::new_transaction_id();
+            let mut b = MessageBuilder::new(method, txid);
+            build(&mut b);
+            let key = match self.state.lock().unwrap().auth.as_ref() {
+                Some(auth) => {
+                    b.attr(codec::ATTR_USERNAME, self.opts.username.as_bytes())
+                        .attr(codec::ATTR_REALM, auth.realm.as_bytes())
+                        .attr(codec::ATTR_NONCE, auth.nonce.as_bytes())
+                        .integrity(&auth.key);
+                    Some(auth.key)
+                }
+                None => None,
+            };
+            let response = self
+                .stun
+                .transaction(&self.sock, self.server, txid, &b.finish())
+                .await?;
+            let msg = Message::parse(&response).ok_or(Error::StunInvalidResponse)?;
+            if msg.method() != method {
+                return Err(Error::StunInvalidResponse);
+            }
+            if msg.is_success() {
+                if let Some(key) = key
+                    && !msg.check_integrity(&key)
+                {
+                    return Err(Error::StunIntegrity);
+                }
+                return Ok(response);
+            }
+            let (code, reason) = msg.error_code().ok_or(Error::StunInvalidResponse)?;
+            let nonce = msg.string(codec::ATTR_NONCE);
+            let retry = match (code, nonce) {
+                // Probably wrong credentials if it happens again.
+                (401, Some(nonce)) if key.is_none() || !retried_unauthorized => {
+                    if key.is_some() {
+                        trace!("TURN server rejected our credentials, retrying with its nonce");
+                        retried_unauthorized = true;
+                    }
+                    let realm = msg
+                        .string(codec::ATTR_REALM)
+                        .ok_or(Error::StunInvalidResponse)?;
+                    let key =
+                        codec::long_term_key(&self.opts.username, &realm, &self.opts.password);
+                    self.state.lock().unwrap().auth = Some(Auth { realm, nonce, key });
+                    true
+                }
+                (438, Some(nonce)) => {
+                    trace!("TURN nonce is stale, retrying");
+                    let mut state = self.state.lock().unwrap();
+                    match state.auth.as_mut() {
+                        Some(auth) => {
+                            auth.nonce = nonce;
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                _ => false,
+            };
+            let e = Error::StunErrorResponse { code, reason };
+            if !retry {
+                return Err(e);
+            }
+            last_error = Some(e);
+        }
+        Err(last_error.unwrap_or(Error::StunInvalidResponse))
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::FutureExt;
+use tokio::{net::UdpSocket as TokioUdpSocket, sync::mpsc, task::JoinHandle, time::timeout};
+
+use super::*;
+use crate::{BindOpts, Error, StunOpts, TurnClient, TurnOpts, UdpSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const USERNAME: &str = "alice";
+const PASSWORD: &str = "secret";
+const REALM: &str = "example.org";
+
+#[derive(Default)]
+struct ServerStats {
+    allocations: usize,
+    refreshes: usize,
+    permissions: HashSet<IpAddr>,
+    channel_data_in: usize,
+    channel_data_out: usize,
+    stale_nonces: usize,
+    unauthorized: usize,
+}
+
+#[derive(Default, Clone, Copy)]
+struct ServerOpts {
+    /// Reject every Nth authenticated request with 438 Stale Nonce.
+    stale_nonce_every: Option<usize>,
+    /// Reject every Nth authenticated request with 401 and a new nonce.
+    unauthorized_every: Option<usize>,
+}
+
+struct ServerAllocation {
+    client: SocketAddr,
+    relay: TokioUdpSocket,
+    channels: HashMap<u16, SocketAddr>,
+}
+
+/// Minimal TURN server with a single allocation.
+struct TurnServer {
+    addr: SocketAddr,
+    stats: Arc<Mutex<ServerStats>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for TurnServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn server_key() -> [u8; 16] {
+    codec::long_term_key(USERNAME, REALM, PASSWORD)
+}
+
+impl TurnServer {
+    async fn start(opts: ServerOpts) -> Self {
+        let sock = TokioUdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = sock.local_addr().unwrap();
+        let stats = Arc::new(Mutex::new(ServerStats::default()));
+        let task = tokio::spawn(run_server(sock, opts, stats.clone()));
+        Self { addr, stats, task }
+    }
+
+    fn stats<T>(&self, f: impl FnOnce(&ServerStats) -> T) -> T {
+        f(&self.stats.lock().unwrap())
+    }
+}
+
+async fn recv_relay(
+    allocation: &Option<ServerAllocation>,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr)> {
+    match allocation {
+        Some(a) => a.relay.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn run_server(sock: TokioUdpSocket, opts: ServerOpts, stats: Arc<Mutex<ServerStats>>) {
+    let mut allocation: Option<ServerAllocation> = None;
+    let mut nonce = 0u32;
+    let mut authenticated = 0usize;
+    let mut buf = [0u8; 2048];
+    let mut relay_buf = [0u8; 2048];
+    loop {
+        tokio::select! {
+            res = sock.recv_from(&mut buf) => {
+                let (sz, from) = res.unwrap();
+                let buf = &buf[..sz];
+                if (0x40..=0x4f).contains(&buf[0]) {
+                    let channel = u16::from_be_bytes([buf[0], buf[1]]);
+                    let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+                    if let Some(a) = &allocation
+                        && let Some(peer) = a.channels.get(&channel)
+                    {
+                        stats.lock().unwrap().channel_data_in += 1;
+                        a.relay.send_to(&buf[4..4 + len], peer).await.unwrap();
+                    }
+                    continue;
+                }
+                let Some(msg) = Message::parse(buf) else { continue };
+                if msg.msg_type == SEND_INDICATION {
+                    let peer = msg.xor_addr(ATTR_XOR_PEER_ADDRESS).unwrap();
+                    if let Some(a) = &allocation
+                        && stats.lock().unwrap().permissions.contains(&peer.ip())
+                    {
+                        a.relay.send_to(msg.attr(ATTR_DATA).unwrap(), peer).await.unwrap();
+                    }
+                    continue;
+                }
+
+                let error = |code: u16, nonce: u32| {
+                    let mut b = MessageBuilder::new(msg.method() | 0x0110, msg.txid);
+                    b.error_code(code, "Test Error")
+                        .attr(codec::ATTR_REALM, REALM.as_bytes())
+                        .attr(codec::ATTR_NONCE, nonce.to_string().as_bytes());
+                    b.finish()
+                };
+                let authed = msg.string(codec::ATTR_USERNAME).as_deref() == Some(USERNAME)
+                    && msg.check_integrity(&server_key());
+                let fresh_nonce = msg.string(codec::ATTR_NONCE) == Some(nonce.to_string());
+                if authed && fresh_nonce {
+                    authenticated += 1;
+                }
+                let nth = |every: Option<usize>| every.is_some_and(|n| authenticated.is_multiple_of(n));
+                let response = if !authed {
+                    error(401, nonce)
+                } else if !fresh_nonce {
+                    error(438, nonce)
+                } else if nth(opts.stale_nonce_every) {
+                    nonce += 1;
+                    stats.lock().unwrap().stale_nonces += 1;
+                    error(438, nonce)
+                } else if nth(opts.unauthorized_every) {
+                    nonce += 1;
+                    stats.lock().unwrap().unauthorized += 1;
+                    error(401, nonce)
+                } else {
+                    let mut b = MessageBuilder::new(msg.method() | 0x0100, msg.txid);
+                    let mut stats = stats.lock().unwrap();
+                    match msg.method() {
+                        ALLOCATE => {
+                            let relay = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+                            relay.set_nonblocking(true).unwrap();
+                            let relay = TokioUdpSocket::from_std(relay).unwrap();
+                            b.xor_addr(ATTR_XOR_RELAYED_ADDRESS, relay.local_addr().unwrap())
+                                .xor_addr(codec::ATTR_XOR_MAPPED_ADDRESS, from)
+                                .attr(ATTR_LIFETIME, msg.attr(ATTR_LIFETIME).unwrap());
+                            allocation = Some(ServerAllocation {
+                                client: from,
+                                relay,
+                                channels: Default::default(),
+                            });
+                            stats.allocations += 1;
+                        }
+                        REFRESH => {
+                            let lifetime = msg.u32(ATTR_LIFETIME).unwrap();
+                            if lifetime == 0 {
+                                allocation = None;
+                                stats.permissions.clear();
+                            }
+                            b.attr(ATTR_LIFETIME, &lifetime.to_be_bytes());
+                            stats.refreshes += 1;
+                        }
+                        CREATE_PERMISSION => {
+                            for peer in msg.xor_addrs(ATTR_XOR_PEER_ADDRESS) {
+                                stats.permissions.insert(peer.ip());
+                            }
+                        }
+                        CHANNEL_BIND => {
+                            let value = msg.attr(ATTR_CHANNEL_NUMBER).unwrap();
+                            let channel = u16::from_be_bytes([value[0], value[1]]);
+                            let peer = msg.xor_addr(ATTR_XOR_PEER_ADDRESS).unwrap();
+                            allocation.as_mut().unwrap().channels.insert(channel, peer);
+                            stats.permissions.insert(peer.ip());
+                        }
+                        _ => continue,
+                    }
+                    b.integrity(&server_key());
+                    b.finish()
+                };
+                sock.send_to(&response, from).await.unwrap();
+            }
+            res = recv_relay(&allocation, &mut relay_buf) => {
+                let (sz, peer) = res.unwrap();
+                let a = allocation.as_ref().unwrap();
+                if !stats.lock().unwrap().permissions.contains(&peer.ip()) {
+                    continue;
+                }
+                let data = &relay_buf[..sz];
+                let msg = match a.channels.iter().find(|(_, p)| **p == peer) {
+                    Some((channel, _)) => {
+                        stats.lock().unwrap().channel_data_out += 1;
+                        let mut msg = channel.to_be_bytes().to_vec();
+                        msg.extend_from_slice(&(sz as u16).to_be_bytes());
+                        msg.extend_from_slice(data);
+                        msg
+                    }
+                    None => {
+                        let mut b = MessageBuilder::new(DATA_INDICATION, codec::new_transaction_id());
+                        b.xor_addr(ATTR_XOR_PEER_ADDRESS, peer).attr(ATTR_DATA, data);
+                        b.finish()
+                    }
+                };
+                sock.send_to(&msg, a.client).await.unwrap();
+            }
+        }
+    }
+}
+
+/// A dualstack socket whose receive loop feeds the TURN client and forwards everything else.
+struct Client {
+    turn: Arc<TurnClient>,
+    other_rx: mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>,
+    sock: Arc<UdpSocket>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn opts() -> TurnOpts {
+    TurnOpts {
+        username: USERNAME.to_owned(),
+        password: PASSWORD.to_owned(),
+        stun: StunOpts {
+            rto: Duration::from_millis(50),
+            max_requests: 4,
+        },
+        ..Default::default()
+    }
+}
+
+fn client(server: SocketAddr, opts: TurnOpts) -> Client {
+    let sock = Arc::new(
+        UdpSocket::bind_udp((Ipv6Addr::UNSPECIFIED, 0).into(), BindOpts::default()).unwrap(),
+    );
+    assert!(sock.is_dualstack());
+    let turn = Arc::new(TurnClient::new(sock.clone(), server, opts));
+    let (tx, other_rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn({
+        let sock = sock.clone();
+        let turn = turn.clone();
+        async move {
+            let mut buf = [0u8; 2048];
+            loop {
+                let (sz, from) = sock.recv_from(&mut buf).await.unwrap();
+                if !turn.handle_datagram(&buf[..sz], from) {
+                    tx.send((buf[..sz].to_vec(), from)).unwrap();
+                }
+            }
+        }
+    });
+    Client {
+        turn,
+        other_rx,
+        sock,
+        task,
+    }
+}
+
+async fn bind_peer() -> (TokioUdpSocket, SocketAddr) {
+    let sock = TokioUdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let addr = sock.local_addr().unwrap();
+    (sock, addr)
+}
+
+async fn recv_peer(sock: &TokioUdpSocket) -> (Vec<u8>, SocketAddr) {
+    let mut buf = [0u8; 2048];
+    let (sz, from) = timeout(TIMEOUT, sock.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    (buf[..sz].to_vec(), from)
+}
+
+async fn recv_relayed(turn: &TurnClient) -> (Vec<u8>, SocketAddr) {
+    let mut buf = [0u8; 2048];
+    let (sz, from) = timeout(TIMEOUT, turn.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    (buf[..sz].to_vec(), from)
+}
+
+fn mapped(addr: SocketAddr) -> SocketAddr {
+    let SocketAddr::V4(v4) = addr else {
+        panic!("{addr:?}")
+    };
+    (v4.ip().to_ipv6_mapped(), v4.port()).into()
+}
+
+#[tokio::test]
+async fn test_allocate_and_relay() {
+    let server = TurnServer::start(Default::default()).await;
+    let mut c = client(mapped(server.addr), opts());
+    assert_eq!(c.turn.server(), server.addr);
+    let relayed = timeout(TIMEOUT, c.turn.allocate()).await.unwrap().unwrap();
+    assert!(relayed.is_ipv4(), "{relayed:?}");
+    assert_eq!(c.turn.relayed_addr(), Some(relayed));
+    assert_eq!(
+        c.turn.mapped_addr(),
+        Some((Ipv4Addr::LOCALHOST, c.sock.bind_addr().port()).into())
+    );
+
+    let (peer, peer_addr) = bind_peer().await;
+    // Creates the permission.
+    c.turn.send_to(b"hello", mapped(peer_addr)).await.unwrap();
+    assert_eq!(recv_peer(&peer).await, (b"hello".to_vec(), relayed));
+    assert!(server.stats(|s| s.permissions.contains(&peer_addr.ip())));
+
+    peer.send_to(b"hi there", relayed).await.unwrap();
+    assert_eq!(
+        recv_relayed(&c.turn).await,
+        (b"hi there".to_vec(), peer_addr)
+    );
+
+    // Other traffic isn't consumed.
+    peer.send_to(b"direct", c.turn.mapped_addr().unwrap())
+        .await
+        .unwrap();
+    let (data, from) = timeout(TIMEOUT, c.other_rx.recv()).await.unwrap().unwrap();
+    assert_eq!((data.as_slice(), from), (&b"direct"[..], peer_addr));
+    assert_eq!(server.stats(|s| s.allocations), 1);
+}
+
+#[tokio::test]
+async fn test_channel_bind() {
+    let server = TurnServer::start(Default::default()).await;
+    let c = client(server.addr, opts());
+    let relayed = timeout(TIMEOUT, c.turn.allocate()).await.unwrap().unwrap();
+    let (peer, peer_addr) = bind_peer().await;
+
+    let channel = c.turn.channel_bind(mapped(peer_addr)).await.unwrap();
+    assert_eq!(channel, 0x4000);
+    // Binding again refreshes the same channel.
+    assert_eq!(c.turn.channel_bind(peer_addr).await.unwrap(), channel);
+    let (_, other_addr) = bind_peer().await;
+    assert_eq!(c.turn.channel_bind(other_addr).await.unwrap(), 0x4001);
+
+    // Concurrent binds pick different channels.
+    let ((_, a), (_, b)) = tokio::join!(bind_peer(), bind_peer());
+    let (a, b) = tokio::join!(c.turn.channel_bind(a), c.turn.channel_bind(b));
+    let mut bound = [a.unwrap(), b.unwrap()];
+    bound.sort();
+    assert_eq!(bound, [0x4002, 0x4003]);
+
+    // A cancelled bind gives its channel back.
+    let (_, cancelled) = bind_peer().await;
+    assert!(c.turn.channel_bind(cancelled).now_or_never().is_none());
+    let (_, next) = bind_peer().await;
+    assert_eq!(c.turn.channel_bind(next).await.unwrap(), 0x4004);
+
+    let err = c
+        .turn
+        .send_to(&vec![0; u16::MAX as usize + 1], peer_addr)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    c.turn.send_to(b"over channel", peer_addr).await.unwrap();
+    assert_eq!(recv_peer(&peer).await, (b"over channel".to_vec(), relayed));
+    peer.send_to(b"back", relayed).await.unwrap();
+    assert_eq!(recv_relayed(&c.turn).await, (b"back".to_vec(), peer_addr));
+    assert_eq!(
+        server.stats(|s| (s.channel_data_in, s.channel_data_out)),
+        (1, 1)
+    );
+}
+
+#[tokio::test]
+async fn test_needs_permission() {
+    let server = TurnServer::start(Default::default()).await;
+    let c = client(server.addr, opts());
+    let relayed = timeout(TIMEOUT, c.turn.allocate()).await.unwrap().unwrap();
+    let (peer, peer_addr) = bind_peer().await;
+
+    peer.send_to(b"dropped", relayed).await.unwrap();
+    let mut buf = [0u8; 16];
+    assert!(
+        timeout(Duration::from_millis(100), c.turn.recv_from(&mut buf))
+            .await
+            .is_err()
+    );
+
+    c.turn.create_permission(&[peer_addr.ip()]).await.unwrap();
+    peer.send_to(b"allowed", relayed).await.unwrap();
+    assert_eq!(
+        recv_relayed(&c.turn).await,
+        (b"allowed".to_vec(), peer_addr)
+    );
+}
+
+#[tokio::test]
+async fn test_wrong_password() {
+    let server = TurnServer::start(Default::default()).await;
+    let c = client(
+        server.addr,
+        TurnOpts {
+            password: "wrong".to_owned(),
+            ..opts()
+        },
+    );
+    let res = timeout(TIMEOUT, c.turn.allocate()).await.unwrap();
+    assert!(
+        matches!(res, Err(Error::StunErrorResponse { code: 401, .. })),
+        "{res:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_unauthorized_with_new_nonce() {
+    let server = TurnServer::start(ServerOpts {
+        unauthorized_every: Some(2),
+        ..Default::default()
+    })
+    .await;
+    let c = client(server.addr, opts());
+    timeout(TIMEOUT, c.turn.allocate()).await.unwrap().unwrap();
+    for i in 1..=4 {
+        c.turn
+            .create_permission(&[Ipv4Addr::new(192, 0, 2, i).into()])
+            .await
+            .unwrap();
+    }
+    assert!(server.stats(|s| s.unauthorized) >= 2);
+    assert_eq!(server.stats(|s| s.permissions.len()), 4);
+}
+
+#[tokio::test]
+async fn test_stale_nonce() {
+    let server = TurnServer::start(ServerOpts {
+        stale_nonce_every: Some(2),
+        ..Default::default()
+    })
+    .await;
+    let c = client(server.addr, opts());
+    timeout(TIMEOUT, c.turn.allocate()).await.unwrap().unwrap();
+    for i in 1..=4 {
+        c.turn
+            .create_permission(&[Ipv4Addr::new(192, 0, 2, i).into()])
+            .await
+            .unwrap();
+    }
+    assert!(server.stats(|s| s.stale_nonces) >= 2);
+    assert_eq!(server.stats(|s| s.permissions.len()), 4);
+}
+
+#[tokio::test]
+async fn test_refresh_and_close() {
+    let server = TurnServer::start(Default::default()).await;
+    let c = client(server.addr, opts());
+    let res = c
+        .turn
+        .create_permission(&[Ipv4Addr::LOCALHOST.into()])
+        .await;
+    assert!(matches!(res, Err(Error::TurnNotAllocated)), "{res:?}");
+
+    timeout(TIMEOUT, c.turn.allocate()).await.unwrap().unwrap();
+    assert_eq!(c.turn.refresh().await.unwrap(), DEFAULT_LIFETIME);
+    c.turn.close().await.unwrap();
+    assert_eq!(server.stats(|s| s.refreshes), 2);
+    assert_eq!(c.turn.relayed_addr(), None);
+    let (_, peer_addr) = bind_peer().await;
+    assert!(c.turn.send_to(b"x", peer_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_maintain() {
+    let server = TurnServer::start(Default::default()).await;
+    let c = client(
+        server.addr,
+        TurnOpts {
+            lifetime: Duration::from_secs(2),
+            ..opts()
+        },
+    );
+    timeout(TIMEOUT, c.turn.allocate()).await.unwrap().unwrap();
+    let maintain = tokio::spawn({
+        let turn = c.turn.clone();
+        async move { turn.maintain().await }
+    });
+    // Refreshed at half the lifetime.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(server.stats(|s| s.refreshes) >= 1);
+    assert!(!maintain.is_finished());
+    maintain.abort();
+    assert!(c.turn.relayed_addr().is_some());
+}
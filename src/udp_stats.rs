@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod tests;
+
+use socket2::SockRef;
+use tracing::{debug, trace};
+
+use crate::{Error, UdpSocket};
+
+/// Kernel-side queue usage and drop counters of a UDP socket (SO_MEMINFO).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UdpSocketStats {
+    /// Bytes currently queued for reading.
+    pub rx_queued: u32,
+    /// Receive buffer size (SO_RCVBUF as reported by the kernel, i.e. doubled).
+    pub rcvbuf: u32,
+    /// Bytes queued for sending.
+    pub tx_queued: u32,
+    pub sndbuf: u32,
+    pub backlog: u32,
+    /// Cumulative number of datagrams dropped by the kernel. None on kernels older than 4.9.
+    pub drops: Option<u32>,
+}
+
+impl UdpSocket {
+    /// Enable SO_RXQ_OVFL, so that [`RecvMeta::drops`](crate::RecvMeta::drops) is reported with
+    /// each received datagram.
+    pub fn set_rxq_ovfl(&self, enable: bool) -> crate::Result<()> {
+        crate::sockopt::set(
+            self.socket(),
+            libc::SOL_SOCKET,
+            libc::SO_RXQ_OVFL,
+            enable as libc::c_int,
+        )
+        .map_err(Error::SetRxqOvfl)
+    }
+
+    pub fn stats(&self) -> crate::Result<UdpSocketStats> {
+        let mut buf = [0u8; (libc::SK_MEMINFO_DROPS as usize + 1) * 4];
+        let len =
+            crate::sockopt::get_buf(self.socket(), libc::SOL_SOCKET, libc::SO_MEMINFO, &mut buf)
+                .map_err(Error::SocketStats)?;
+        let field = |idx: libc::c_int| {
+            let start = idx as usize * 4;
+            buf.get(start..start + 4)
+                .filter(|_| start + 4 <= len)
+                .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+        };
+        Ok(UdpSocketStats {
+            rx_queued: field(libc::SK_MEMINFO_RMEM_ALLOC).unwrap_or_default(),
+            rcvbuf: field(libc::SK_MEMINFO_RCVBUF).unwrap_or_default(),
+            tx_queued: field(libc::SK_MEMINFO_WMEM_ALLOC).unwrap_or_default(),
+            sndbuf: field(libc::SK_MEMINFO_SNDBUF).unwrap_or_default(),
+            backlog: field(libc::SK_MEMINFO_BACKLOG).unwrap_or_default(),
+            drops: field(libc::SK_MEMINFO_DROPS),
+        })
+    }
+}
+
+/// Grows SO_RCVBUF of a socket (up to a cap) whenever the kernel starts dropping datagrams.
+///
+/// Call [`tick`](Self::tick) periodically.
+#[derive(Debug)]
+pub struct RcvbufAutotuner {
+    max_rcvbuf: usize,
+    last_drops: Option<u32>,
+}
+
+impl RcvbufAutotuner {
+    /// `max_rcvbuf` is in the same units as [`UdpSocketStats::rcvbuf`]. Unprivileged processes
+    /// are also capped by net.core.rmem_max.
+    pub fn new(max_rcvbuf: usize) -> Self {
+        Self {
+            max_rcvbuf,
+            last_drops: None,
+        }
+    }
+
+    /// Returns the new buffer size if it was changed.
+    pub fn tick(&mut self, sock: &UdpSocket) -> crate::Result<Option<usize>> {
+        let stats = sock.stats()?;
+        let Some(drops) = stats.drops else {
+            trace!("kernel doesn't report drops, can't autotune SO_RCVBUF");
+            return Ok(None);
+        };
+        let prev = self.last_drops.replace(drops);
+        let new_drops = match prev {
+            Some(prev) => drops.wrapping_sub(prev),
+            None => return Ok(None),
+        };
+        let current = stats.rcvbuf as usize;
+        if new_drops == 0 || current >= self.max_rcvbuf {
+            return Ok(None);
+        }
+
+        let target = current.saturating_mul(2).min(self.max_rcvbuf);
+        // The kernel doubles the requested value to account for bookkeeping overhead.
+        SockRef::from(sock.socket())
+            .set_recv_buffer_size(target / 2)
+            .map_err(Error::SetRecvBufferSize)?;
+        let new = sock.stats()?.rcvbuf as usize;
+        if new <= current {
+            debug!(
+                current,
+                requested = target,
+                "could not grow SO_RCVBUF, probably limited by net.core.rmem_max"
+            );
+            return Ok(None);
+        }
+        debug!(
+            addr=?sock.bind_addr(),
+            new_drops,
+            old = current,
+            new,
+            "grew SO_RCVBUF after kernel drops"
+        );
+        Ok(Some(new))
+    }
+}
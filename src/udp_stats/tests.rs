@@ -0,0 +1,84 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use socket2::SockRef;
+use tokio::time::timeout;
+
+use crate::{BindOpts, RcvbufAutotuner, UdpSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+fn bind_small_rcvbuf() -> UdpSocket {
+    let sock = UdpSocket::bind_udp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    SockRef::from(sock.socket())
+        .set_recv_buffer_size(4096)
+        .unwrap();
+    sock
+}
+
+async fn flood(target: &UdpSocket, count: usize) {
+    let sender = UdpSocket::bind_udp((Ipv4Addr::LOCALHOST, 0).into(), BindOpts::default()).unwrap();
+    let buf = [0u8; 1000];
+    for _ in 0..count {
+        sender.send_to(&buf, target.bind_addr()).await.unwrap();
+    }
+}
+
+async fn drain(sock: &UdpSocket) {
+    let mut buf = [0u8; 1000];
+    while timeout(Duration::from_millis(10), sock.recv_from(&mut buf))
+        .await
+        .is_ok()
+    {}
+}
+
+#[tokio::test]
+async fn test_stats_and_drops() {
+    let sock = bind_small_rcvbuf();
+    sock.set_rxq_ovfl(true).unwrap();
+    let stats = sock.stats().unwrap();
+    assert!(stats.rcvbuf > 0);
+    assert_eq!(stats.rx_queued, 0);
+    assert_eq!(stats.drops, Some(0));
+
+    flood(&sock, 100).await;
+    let stats = sock.stats().unwrap();
+    assert!(stats.rx_queued > 0);
+    let drops = stats.drops.unwrap();
+    assert!(drops > 0);
+
+    drain(&sock).await;
+    assert_eq!(sock.stats().unwrap().rx_queued, 0);
+
+    flood(&sock, 1).await;
+    let mut buf = [0u8; 1000];
+    let (_, _, meta) = timeout(TIMEOUT, sock.recv_from_with_meta(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(meta.drops, Some(drops));
+}
+
+#[tokio::test]
+async fn test_autotuner_grows_rcvbuf() {
+    let sock = bind_small_rcvbuf();
+    let initial = sock.stats().unwrap().rcvbuf as usize;
+    let max = initial * 4;
+    let mut tuner = RcvbufAutotuner::new(max);
+
+    assert_eq!(tuner.tick(&sock).unwrap(), None);
+    assert_eq!(tuner.tick(&sock).unwrap(), None, "no drops, no changes");
+
+    flood(&sock, 100).await;
+    let grown = tuner.tick(&sock).unwrap().expect("expected rcvbuf to grow");
+    assert!(grown > initial && grown <= max, "{grown} {initial} {max}");
+
+    drain(&sock).await;
+    flood(&sock, 100).await;
+    let grown = tuner.tick(&sock).unwrap().expect("expected rcvbuf to grow");
+    assert_eq!(grown, max);
+
+    drain(&sock).await;
+    flood(&sock, 200).await;
+    assert_eq!(tuner.tick(&sock).unwrap(), None, "capped");
+}
@@ -0,0 +1,306 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+};
+
+use socket2::SockRef;
+use tokio::{io::AsyncWriteExt, io::Interest, net::TcpStream};
+use tracing::{debug, trace};
+
+use crate::{Error, cmsg};
+
+// From linux/errqueue.h, missing in libc.
+const SO_EE_ORIGIN_ZEROCOPY: u8 = 5;
+const SO_EE_CODE_ZEROCOPY_COPIED: u8 = 1;
+
+/// A buffer whose bytes stay at the same address when the buffer itself is moved, i.e. an owner of
+/// heap memory.
+///
+/// # Safety
+///
+/// The kernel keeps reading from the address of `as_ref()` after the send returns, while the
+/// buffer is moved into the sender's queue. Implementing this for types that store their bytes
+/// inline (arrays, SmallVec, ArrayVec) would send whatever later overwrites the old location.
+pub unsafe trait ZerocopyBuf: AsRef<[u8]> {}
+
+unsafe impl ZerocopyBuf for Vec<u8> {}
+unsafe impl ZerocopyBuf for Box<[u8]> {}
+unsafe impl ZerocopyBuf for Arc<[u8]> {}
+unsafe impl ZerocopyBuf for &'static [u8] {}
+
+struct Pending<B> {
+    // Notification ids of the sendmsg() calls that transmitted the buffer.
+    ids: Vec<u32>,
+    buf: B,
+}
+
+// Leaks buffers the kernel may still be reading when the sender is dropped, instead of freeing
+// memory that could be reused before it's sent.
+struct PendingQueue<B>(VecDeque<Pending<B>>);
+
+impl<B> Drop for PendingQueue<B> {
+    fn drop(&mut self) {
+        if !self.0.is_empty() {
+            debug!(
+                count = self.0.len(),
+                "dropping ZerocopySender with pending buffers, leaking them"
+            );
+        }
+        for pending in self.0.drain(..) {
+            std::mem::forget(pending.buf);
+        }
+    }
+}
+
+// A completed range of notification ids.
+struct Completion {
+    lo: u32,
+    hi: u32,
+    copied: bool,
+}
+
+/// Sends buffers on a TCP stream with MSG_ZEROCOPY, without copying them into the kernel.
+///
+/// The kernel keeps reading a buffer after [`send`](Self::send) returns, so it is only handed back
+/// through [`completed`](Self::completed) once the kernel is done with it. If the kernel reports
+/// it had to copy anyway (e.g. on loopback, or if the NIC can't do scatter-gather), the sender
+/// falls back to regular sends, as those are cheaper than zerocopy with copying.
+///
+/// Dropping the sender leaks the buffers that are still pending. Use
+/// [`into_inner`](Self::into_inner) to get them back.
+pub struct ZerocopySender<B> {
+    stream: TcpStream,
+    zerocopy: bool,
+    next_id: u32,
+    outstanding: HashSet<u32>,
+    pending: PendingQueue<B>,
+}
+
+impl<B: ZerocopyBuf> ZerocopySender<B> {
+    /// Enable SO_ZEROCOPY on the stream. Falls back to regular sends if the kernel doesn't
+    /// support it.
+    pub fn new(stream: TcpStream) -> crate::Result<Self> {
+        let zerocopy = match crate::sockopt::set(
+            &stream,
+            libc::SOL_SOCKET,
+            libc::SO_ZEROCOPY,
+            1 as libc::c_int,
+        ) {
+            Ok(()) => true,
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::ENOPROTOOPT) | Some(libc::EOPNOTSUPP)
+                ) =>
+            {
+                debug!("SO_ZEROCOPY not supported, falling back to copying: {e:#}");
+                false
+            }
+            Err(e) => return Err(Error::Zerocopy(e)),
+        };
+        Ok(Self {
+            stream,
+            zerocopy,
+            next_id: 0,
+            outstanding: Default::default(),
+            pending: PendingQueue(VecDeque::new()),
+        })
+    }
+
+    /// False once the kernel reported a copied completion, or if SO_ZEROCOPY isn't supported.
+    pub fn is_zerocopy(&self) -> bool {
+        self.zerocopy
+    }
+
+    /// Number of buffers not yet returned by [`completed`](Self::completed).
+    pub fn pending(&self) -> usize {
+        self.pending.0.len()
+    }
+
+    /// Write the whole buffer. It's returned by [`completed`](Self::completed) when the kernel no
+    /// longer needs it, including when the send fails or the future is dropped part way through.
+    pub async fn send(&mut self, buf: B) -> std::io::Result<()> {
+        let len = buf.as_ref().len();
+        // Queue the buffer before the first send, so every early exit leaves it with the sender
+        // until the kernel is done reading it.
+        self.pending.0.push_back(Pending {
+            ids: Vec::new(),
+            buf,
+        });
+        let mut written = 0;
+        while written < len {
+            let data = &self.pending.0.back().expect("queued above").buf.as_ref()[written..];
+            if !self.zerocopy {
+                self.stream.write_all(data).await?;
+                break;
+            }
+            let sref = SockRef::from(&self.stream);
+            let res = self
+                .stream
+                .async_io(Interest::WRITABLE, || {
+                    sref.send_with_flags(data, libc::MSG_ZEROCOPY)
+                })
+                .await;
+            match res {
+                Ok(sz) => {
+                    // Every successful MSG_ZEROCOPY send gets the next notification id.
+                    let id = self.next_id;
+                    self.pending
+                        .0
+                        .back_mut()
+                        .expect("queued above")
+                        .ids
+                        .push(id);
+                    self.outstanding.insert(id);
+                    self.next_id = id.wrapping_add(1);
+                    written += sz;
+                }
+                // Too many notifications are queued, wait for some to be read.
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    if self.outstanding.is_empty() {
+                        debug!(
+                            "MSG_ZEROCOPY failed with nothing outstanding, falling back to copying"
+                        );
+                        self.zerocopy = false;
+                        continue;
+                    }
+                    let completion = self.recv_completion().await?;
+                    self.complete(completion);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the next buffer the kernel is done with, in the order they were sent, without
+    /// waiting.
+    pub fn try_completed(&mut self) -> std::io::Result<Option<B>> {
+        loop {
+            if let Some(buf) = self.pop_completed() {
+                return Ok(Some(buf));
+            }
+            if self.pending.0.is_empty() {
+                return Ok(None);
+            }
+            match try_recv_completion(&self.stream)? {
+                Some(completion) => self.complete(completion),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Wait for the next buffer the kernel is done with, in the order they were sent. None if
+    /// nothing is pending.
+    pub async fn completed(&mut self) -> std::io::Result<Option<B>> {
+        loop {
+            if let Some(buf) = self.pop_completed() {
+                return Ok(Some(buf));
+            }
+            if self.pending.0.is_empty() {
+                return Ok(None);
+            }
+            let completion = self.recv_completion().await?;
+            self.complete(completion);
+        }
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub fn stream_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Wait until the kernel is done with all pending buffers, and return them with the stream.
+    ///
+    /// Dropping a buffer earlier would let the allocator hand its memory out again while the
+    /// kernel may still be sending from it.
+    pub async fn into_inner(mut self) -> std::io::Result<(TcpStream, Vec<B>)> {
+        let mut bufs = Vec::with_capacity(self.pending.0.len());
+        while let Some(buf) = self.completed().await? {
+            bufs.push(buf);
+        }
+        Ok((self.stream, bufs))
+    }
+
+    fn pop_completed(&mut self) -> Option<B> {
+        let front = self.pending.0.front()?;
+        if front.ids.iter().any(|id| self.outstanding.contains(id)) {
+            return None;
+        }
+        self.pending.0.pop_front().map(|p| p.buf)
+    }
+
+    fn complete(&mut self, completion: Completion) {
+        trace!(
+            lo = completion.lo,
+            hi = completion.hi,
+            copied = completion.copied,
+            "zerocopy completion"
+        );
+        let mut id = completion.lo;
+        loop {
+            self.outstanding.remove(&id);
+            if id == completion.hi {
+                break;
+            }
+            id = id.wrapping_add(1);
+        }
+        if completion.copied && self.zerocopy {
+            debug!("kernel copied zerocopy sends, falling back to copying");
+            self.zerocopy = false;
+        }
+    }
+
+    async fn recv_completion(&self) -> std::io::Result<Completion> {
+        self.stream
+            .async_io(Interest::ERROR, || {
+                try_recv_completion(&self.stream)?
+                    .ok_or_else(|| std::io::ErrorKind::WouldBlock.into())
+            })
+            .await
+    }
+}
+
+fn try_recv_completion(stream: &TcpStream) -> std::io::Result<Option<Completion>> {
+    loop {
+        let cmsg = match cmsg::recv_errqueue(SockRef::from(stream)) {
+            Ok(cmsg) => cmsg,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        for (level, ty, data) in cmsg.iter() {
+            // The level depends on the socket family, not on the peer (mapped IPv4 peers use IPv6).
+            if !matches!(
+                (level, ty),
+                (libc::SOL_IP, libc::IP_RECVERR) | (libc::SOL_IPV6, libc::IPV6_RECVERR)
+            ) || data.len() < std::mem::size_of::<libc::sock_extended_err>()
+            {
+                continue;
+            }
+            let err = unsafe {
+                data.as_ptr()
+                    .cast::<libc::sock_extended_err>()
+                    .read_unaligned()
+            };
+            if err.ee_origin != SO_EE_ORIGIN_ZEROCOPY || err.ee_errno != 0 {
+                trace!(
+                    origin = err.ee_origin,
+                    errno = err.ee_errno,
+                    "ignoring error queue message"
+                );
+                continue;
+            }
+            return Ok(Some(Completion {
+                lo: err.ee_info,
+                hi: err.ee_data,
+                copied: err.ee_code & SO_EE_CODE_ZEROCOPY_COPIED != 0,
+            }));
+        }
+    }
+}
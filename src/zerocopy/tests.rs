@@ -0,0 +1,131 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::{io::AsyncReadExt, net::TcpStream, time::timeout};
+
+use crate::{BindOpts, TcpListener, ZerocopySender};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const CHUNK: usize = 256 * 1024;
+
+async fn connected_pair(listen: SocketAddr, connect_ip: Ipv4Addr) -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind_tcp(listen, BindOpts::default()).unwrap();
+    let port = listener.bind_addr().port();
+    let (client, accepted) =
+        tokio::join!(TcpStream::connect((connect_ip, port)), listener.accept());
+    (client.unwrap(), accepted.unwrap().0)
+}
+
+fn chunk(i: u8) -> Vec<u8> {
+    (0..CHUNK).map(|j| (j as u8).wrapping_add(i)).collect()
+}
+
+async fn read_all(mut stream: TcpStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await.unwrap();
+    buf
+}
+
+async fn check_send(sender: TcpStream, receiver: TcpStream) {
+    let mut zc = ZerocopySender::new(sender).unwrap();
+    assert!(zc.is_zerocopy());
+    let reader = tokio::spawn(read_all(receiver, 5 * CHUNK));
+
+    for i in 0..4 {
+        timeout(TIMEOUT, zc.send(chunk(i))).await.unwrap().unwrap();
+    }
+    for i in 0..4 {
+        let buf = timeout(TIMEOUT, zc.completed()).await.unwrap().unwrap();
+        assert_eq!(buf, Some(chunk(i)));
+    }
+    assert_eq!(zc.pending(), 0);
+    assert!(
+        timeout(TIMEOUT, zc.completed())
+            .await
+            .unwrap()
+            .unwrap()
+            .is_none()
+    );
+    // Loopback always copies.
+    assert!(!zc.is_zerocopy());
+
+    // Copied sends complete right away.
+    timeout(TIMEOUT, zc.send(chunk(4))).await.unwrap().unwrap();
+    assert_eq!(zc.try_completed().unwrap(), Some(chunk(4)));
+
+    let received = timeout(TIMEOUT, reader).await.unwrap().unwrap();
+    for i in 0..5 {
+        assert!(
+            received[i * CHUNK..(i + 1) * CHUNK] == chunk(i as u8),
+            "chunk {i}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_zerocopy_dualstack_mapped() {
+    let (client, accepted) =
+        connected_pair((Ipv6Addr::UNSPECIFIED, 0).into(), Ipv4Addr::LOCALHOST).await;
+    assert!(accepted.local_addr().unwrap().is_ipv6());
+    check_send(accepted, client).await;
+}
+
+#[tokio::test]
+async fn test_zerocopy_ipv4() {
+    let (client, accepted) =
+        connected_pair((Ipv4Addr::LOCALHOST, 0).into(), Ipv4Addr::LOCALHOST).await;
+    check_send(client, accepted).await;
+}
+
+#[tokio::test]
+async fn test_zerocopy_nothing_pending() {
+    let (client, _accepted) =
+        connected_pair((Ipv4Addr::LOCALHOST, 0).into(), Ipv4Addr::LOCALHOST).await;
+    let mut zc = ZerocopySender::<Vec<u8>>::new(client).unwrap();
+    assert_eq!(zc.try_completed().unwrap(), None);
+    assert_eq!(zc.completed().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_zerocopy_into_inner_returns_pending() {
+    let (client, accepted) =
+        connected_pair((Ipv4Addr::LOCALHOST, 0).into(), Ipv4Addr::LOCALHOST).await;
+    let reader = tokio::spawn(read_all(accepted, 2 * CHUNK));
+    let mut zc = ZerocopySender::new(client).unwrap();
+    for i in 0..2 {
+        timeout(TIMEOUT, zc.send(chunk(i))).await.unwrap().unwrap();
+    }
+    let (_stream, bufs) = timeout(TIMEOUT, zc.into_inner()).await.unwrap().unwrap();
+    assert_eq!(bufs, vec![chunk(0), chunk(1)]);
+    timeout(TIMEOUT, reader).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_zerocopy_dropped_send_keeps_buffer() {
+    let (client, mut accepted) =
+        connected_pair((Ipv4Addr::LOCALHOST, 0).into(), Ipv4Addr::LOCALHOST).await;
+    let mut zc = ZerocopySender::new(client).unwrap();
+    // Much more than the socket buffers hold, so the send blocks part way through while nothing
+    // is read.
+    let big: Vec<u8> = (0..64 * CHUNK).map(|j| j as u8).collect();
+    assert!(
+        timeout(Duration::from_millis(200), zc.send(big.clone()))
+            .await
+            .is_err()
+    );
+    assert_eq!(zc.pending(), 1);
+
+    let reader = tokio::spawn(async move {
+        let mut received = Vec::new();
+        accepted.read_to_end(&mut received).await.unwrap();
+        received
+    });
+    let buf = timeout(TIMEOUT, zc.completed()).await.unwrap().unwrap();
+    assert_eq!(buf.as_ref(), Some(&big));
+    assert_eq!(zc.pending(), 0);
+    drop(zc);
+
+    let received = timeout(TIMEOUT, reader).await.unwrap().unwrap();
+    assert!(!received.is_empty() && received.len() < big.len());
+    assert!(received[..] == big[..received.len()]);
+}